@@ -0,0 +1,767 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use futures_util::future::join_all;
+
+use crate::{
+    config::{
+        bridge_key, device_key, ConfigError, HueConfig, LayoutConfig, MappingConfig, NanoHueConfig,
+        NanoleafConfig, SourceKind, DEFAULT_NAME,
+    },
+    discovery::{self, DeviceKind},
+    hue::{
+        cache::ResourceCache,
+        client::Hue,
+        error::HueError,
+        types::{Light, Room},
+    },
+    mirror::{Mirror, Output, Source},
+    nanoleaf::{client::Nanoleaf, error::NanoleafError},
+    spatial::{Position, SpatialLayout, SpatialLight},
+};
+
+/// A client for a bridge, along with a mirror for each mapping whose source
+/// lives on it.
+pub struct Bridge {
+    pub name: String,
+    pub hue: Hue,
+    pub cache: ResourceCache,
+    pub bridge_id: Option<String>,
+    pub mirrors: Vec<Mirror>,
+}
+
+/// Clients for every device, once the resources the configuration refers to
+/// have been confirmed to exist.
+pub struct Connection {
+    pub bridges: Vec<Bridge>,
+}
+
+/// Create a client for the configured bridge, looking it up by ID if needed.
+pub async fn connect_hue(config: &HueConfig) -> Result<Hue, Box<dyn std::error::Error>> {
+    let host = discovery::resolve_host(
+        DeviceKind::HueBridge,
+        config.host.as_deref(),
+        config.bridge_id.as_deref(),
+    )
+    .await?;
+
+    Ok(Hue::new(host, config.username.clone())?)
+}
+
+/// Create a client for the configured Nanoleaf, looking it up by ID if needed.
+pub async fn connect_nanoleaf(
+    config: &NanoleafConfig,
+) -> Result<Nanoleaf, Box<dyn std::error::Error>> {
+    let host = discovery::resolve_host(
+        DeviceKind::Nanoleaf,
+        config.host.as_deref(),
+        config.device_id.as_deref(),
+    )
+    .await?;
+
+    Ok(Nanoleaf::new(host, config.token.clone())?)
+}
+
+fn describe_hue_error(key: &str, err: HueError) -> String {
+    match err {
+        HueError::Unauthorized => format!(
+            "the Hue bridge rejected {}.username; run `nanohue pair-hue` to create a new one",
+            key
+        ),
+        err => format!("{}: {}", key, err),
+    }
+}
+
+fn describe_nanoleaf_error(key: &str, err: NanoleafError) -> String {
+    match err {
+        NanoleafError::Unauthorized => format!(
+            "the Nanoleaf rejected {}.token; run `nanohue pair-nanoleaf` to create a new one",
+            key
+        ),
+        err => format!("{}: {}", key, err),
+    }
+}
+
+fn not_found(kind: SourceKind, name: &str, available: Vec<&str>) -> String {
+    format!(
+        "{} '{}' not found; available {}s: {}",
+        kind,
+        name,
+        kind,
+        available.join(", ")
+    )
+}
+
+/// Find the entertainment area a layout refers to, and where its lights are.
+/// The panels hang on a wall, so the area's height is used for `y`.
+async fn check_entertainment(
+    hue: &Hue,
+    cache: &ResourceCache,
+    name: &str,
+) -> Result<(String, Vec<(String, Position)>), String> {
+    let areas = cache.entertainment_configurations();
+    let services = hue
+        .entertainment_services()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let Some(area) = areas.iter().find(|area| area.metadata.name == name) else {
+        let available: Vec<&str> = areas
+            .iter()
+            .map(|area| area.metadata.name.as_str())
+            .collect();
+        return Err(format!(
+            "entertainment area '{}' not found; available entertainment areas: {}",
+            name,
+            available.join(", ")
+        ));
+    };
+
+    let positions = area
+        .light_positions(&services)
+        .into_iter()
+        .map(|(id, position)| {
+            (
+                id,
+                Position {
+                    x: position.x.clamp(-1.0, 1.0),
+                    y: position.z.clamp(-1.0, 1.0),
+                },
+            )
+        })
+        .collect();
+
+    Ok((area.id.clone(), positions))
+}
+
+/// Resolve the light names in a layout to the lights in the room or zone.
+async fn check_layout(
+    hue: &Hue,
+    cache: &ResourceCache,
+    kind: SourceKind,
+    group: &Room,
+    layout: &LayoutConfig,
+) -> Result<SpatialLayout, String> {
+    let members: Vec<&Light> = cache
+        .lights()
+        .iter()
+        .filter(|light| group.contains_light(light))
+        .collect();
+
+    let find = |name: &str| {
+        members
+            .iter()
+            .find(|light| light.metadata.name == name)
+            .map(|light| light.id.clone())
+            .ok_or_else(|| {
+                let available: Vec<&str> = members
+                    .iter()
+                    .map(|light| light.metadata.name.as_str())
+                    .collect();
+                format!(
+                    "layout refers to '{}', which is not in {} '{}'; its lights are: {}",
+                    name,
+                    kind,
+                    group.metadata.name,
+                    available.join(", ")
+                )
+            })
+    };
+
+    let mut spatial_lights: Vec<SpatialLight> = Vec::new();
+    for (name, position) in &layout.lights {
+        spatial_lights.push(SpatialLight {
+            id: find(name)?,
+            position: Some(*position),
+        });
+    }
+
+    let mut entertainment = None;
+    if let Some(name) = &layout.entertainment {
+        let (id, positions) = check_entertainment(hue, cache, name).await?;
+
+        let mut found = false;
+        for (light_id, position) in positions {
+            if !members.iter().any(|light| light.id == light_id) {
+                continue;
+            }
+            found = true;
+
+            if !spatial_lights.iter().any(|light| light.id == light_id) {
+                spatial_lights.push(SpatialLight {
+                    id: light_id,
+                    position: Some(position),
+                });
+            }
+        }
+
+        if !found {
+            return Err(format!(
+                "none of the lights in entertainment area '{}' are in {} '{}'",
+                name, kind, group.metadata.name
+            ));
+        }
+
+        entertainment = Some(id);
+    }
+
+    let mut panels = BTreeMap::new();
+    for (panel_id, name) in &layout.panels {
+        let id = find(name)?;
+        let index = match spatial_lights.iter().position(|light| light.id == id) {
+            Some(index) => index,
+            None => {
+                spatial_lights.push(SpatialLight { id, position: None });
+                spatial_lights.len() - 1
+            }
+        };
+        panels.insert(*panel_id, index);
+    }
+
+    Ok(SpatialLayout {
+        mode: layout.mode,
+        lights: spatial_lights,
+        panels,
+        transition_time: layout.transition_time,
+        entertainment,
+    })
+}
+
+/// Find the resource a mapping refers to, making sure rooms and zones have a
+/// grouped light to follow.
+async fn check_source(
+    hue: &Hue,
+    cache: &ResourceCache,
+    mapping: &MappingConfig,
+) -> Result<(Source, Option<SpatialLayout>), String> {
+    let (kind, name) = mapping
+        .source()
+        .expect("mappings are validated before connecting");
+
+    let group = match kind {
+        SourceKind::Light => {
+            let matches: Vec<&Light> = cache
+                .lights()
+                .iter()
+                .filter(|light| light.metadata.name == name)
+                .collect();
+
+            return match matches.as_slice() {
+                [light] => Ok((Source::Light(Box::new((*light).clone())), None)),
+                [] => Err(not_found(
+                    kind,
+                    name,
+                    cache
+                        .lights()
+                        .iter()
+                        .map(|light| light.metadata.name.as_str())
+                        .collect(),
+                )),
+                _ => Err(format!(
+                    "{} lights are named '{}'; rename them so they can be told apart",
+                    matches.len(),
+                    name
+                )),
+            };
+        }
+        SourceKind::Room => cache.rooms(),
+        SourceKind::Zone => cache.zones(),
+    };
+
+    let Some(group) = group.iter().find(|group| group.metadata.name == name) else {
+        return Err(not_found(
+            kind,
+            name,
+            group
+                .iter()
+                .map(|group| group.metadata.name.as_str())
+                .collect(),
+        ));
+    };
+
+    let grouped_light = group
+        .services
+        .iter()
+        .find(|service| service.resource_type == "grouped_light")
+        .map(|service| service.id.clone())
+        .ok_or_else(|| format!("{} '{}' has no grouped light", kind, name))?;
+
+    if cache.grouped_light(&grouped_light).is_none() {
+        return Err(format!(
+            "{} '{}' has a grouped light the bridge doesn't list",
+            kind, name
+        ));
+    }
+
+    let layout = match &mapping.layout {
+        Some(layout) => Some(check_layout(hue, cache, kind, group, layout).await?),
+        None => None,
+    };
+
+    Ok((
+        Source::Group {
+            kind,
+            group: group.clone(),
+            grouped_light,
+        },
+        layout,
+    ))
+}
+
+async fn check_hue(
+    name: &str,
+    config: &HueConfig,
+    mappings: &[&MappingConfig],
+    dry_run: bool,
+) -> Result<(Hue, ResourceCache, Vec<(Source, Option<SpatialLayout>)>), Vec<String>> {
+    let key = bridge_key(name);
+    let hue = connect_hue(config)
+        .await
+        .map_err(|err| vec![format!("{}: {}", key, err)])?
+        .with_dry_run(dry_run);
+
+    let cache = ResourceCache::load(&hue)
+        .await
+        .map_err(|err| vec![describe_hue_error(&key, err)])?;
+
+    let results = join_all(
+        mappings
+            .iter()
+            .map(|mapping| check_source(&hue, &cache, mapping)),
+    )
+    .await;
+
+    let mut found = Vec::new();
+    let mut problems = Vec::new();
+    for result in results {
+        match result {
+            Ok(source) => found.push(source),
+            Err(problem) => problems.push(format!("{}: {}", key, problem)),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok((hue, cache, found))
+    } else {
+        Err(problems)
+    }
+}
+
+async fn check_nanoleaf(
+    name: &str,
+    config: &NanoleafConfig,
+    dry_run: bool,
+) -> Result<Output, String> {
+    let key = device_key(name);
+    let nanoleaf = connect_nanoleaf(config)
+        .await
+        .map_err(|err| format!("{}: {}", key, err))?
+        .with_dry_run(dry_run);
+    let panel = nanoleaf
+        .get_panel()
+        .await
+        .map_err(|err| describe_nanoleaf_error(&key, err))?;
+
+    Ok(Output {
+        name: name.to_string(),
+        panel_name: panel.name,
+        nanoleaf: Arc::new(nanoleaf),
+        device_id: config.device_id.clone(),
+        layout: panel.panel_layout,
+        resolving: Arc::default(),
+    })
+}
+
+/// Connect to every Nanoleaf used by a mapping, once each.
+async fn check_nanoleafs(
+    config: &NanoHueConfig,
+    mappings: &[MappingConfig],
+    dry_run: bool,
+) -> Result<BTreeMap<String, Output>, Vec<String>> {
+    let devices = config.devices();
+    let used: BTreeMap<&str, &NanoleafConfig> = mappings
+        .iter()
+        .flat_map(|mapping| mapping.nanoleaf.iter())
+        .filter_map(|name| devices.get_key_value(name.as_str()))
+        .map(|(name, device)| (*name, *device))
+        .collect();
+
+    let results = join_all(
+        used.iter()
+            .map(|(name, device)| check_nanoleaf(name, device, dry_run)),
+    )
+    .await;
+
+    let mut outputs = BTreeMap::new();
+    let mut problems = Vec::new();
+    for result in results {
+        match result {
+            Ok(output) => {
+                outputs.insert(output.name.clone(), output);
+            }
+            Err(problem) => problems.push(problem),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(outputs)
+    } else {
+        Err(problems)
+    }
+}
+
+/// Validate the configuration, then make sure every device is reachable and
+/// that the configured rooms, zones and lights exist.
+pub async fn connect(config: &NanoHueConfig, dry_run: bool) -> Result<Connection, ConfigError> {
+    config.validate()?;
+
+    let mappings = config.mappings();
+    let bridges = config.bridges();
+
+    // Group the mappings by bridge, keeping them in the order they were configured.
+    let mut by_bridge: Vec<(&str, Vec<&MappingConfig>)> = Vec::new();
+    for mapping in &mappings {
+        match by_bridge
+            .iter_mut()
+            .find(|(name, _)| *name == mapping.bridge())
+        {
+            Some((_, mappings)) => mappings.push(mapping),
+            None => by_bridge.push((mapping.bridge(), vec![mapping])),
+        }
+    }
+
+    let (hues, nanoleafs) = tokio::join!(
+        join_all(by_bridge.iter().map(|(name, mappings)| check_hue(
+            name,
+            bridges[name],
+            mappings,
+            dry_run
+        )),),
+        check_nanoleafs(config, &mappings, dry_run)
+    );
+
+    let mut problems: Vec<String> = hues
+        .iter()
+        .filter_map(|hue| hue.as_ref().err())
+        .flatten()
+        .cloned()
+        .collect();
+    let outputs = match nanoleafs {
+        Ok(outputs) => outputs,
+        Err(nanoleaf_problems) => {
+            problems.extend(nanoleaf_problems);
+            BTreeMap::new()
+        }
+    };
+
+    // Panels can only be assigned once the controllers' layouts are known.
+    for ((name, mappings), hue) in by_bridge.iter().zip(&hues) {
+        let Ok((_, _, sources)) = hue else {
+            continue;
+        };
+
+        for (mapping, (_, layout)) in mappings.iter().zip(sources) {
+            let Some(layout) = layout else {
+                continue;
+            };
+
+            for panel_id in layout.panels.keys() {
+                let found = mapping
+                    .nanoleaf
+                    .iter()
+                    .filter_map(|device| outputs.get(device))
+                    .flat_map(|output| &output.layout.layout.position_data)
+                    .any(|panel| panel.panel_id == *panel_id && panel.is_lit());
+
+                if !found {
+                    problems.push(format!(
+                        "{}: panel {} in the layout for {} isn't a lit panel on {}; run `nanohue nanoleaf identify` to find their IDs",
+                        bridge_key(name),
+                        panel_id,
+                        mapping.source().map_or(String::new(), |(kind, name)| format!("{} '{}'", kind, name)),
+                        mapping.nanoleaf.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(ConfigError(problems));
+    }
+
+    let bridges = by_bridge
+        .into_iter()
+        .zip(hues.into_iter().flatten())
+        .map(|((name, mappings), (hue, cache, sources))| {
+            let mirrors = mappings
+                .into_iter()
+                .zip(sources)
+                .map(|(mapping, (source, layout))| {
+                    let outputs = mapping
+                        .nanoleaf
+                        .iter()
+                        .map(|name| outputs[name].clone())
+                        .collect();
+
+                    Mirror::new(
+                        source,
+                        outputs,
+                        config.max_brightness(mapping),
+                        mapping.effect.clone(),
+                        layout,
+                        mapping.reverse_sync,
+                    )
+                })
+                .collect();
+
+            Bridge {
+                name: name.to_string(),
+                hue,
+                cache,
+                bridge_id: bridges[name].bridge_id.clone(),
+                mirrors,
+            }
+        })
+        .collect();
+
+    Ok(Connection { bridges })
+}
+
+/// A one-line description of where a mirror's source is being sent.
+pub fn describe_mirror(bridge: &Bridge, mirror: &Mirror) -> String {
+    let outputs: Vec<String> = mirror
+        .outputs()
+        .iter()
+        .map(|output| format!("'{}' ({})", output.panel_name, output.name))
+        .collect();
+
+    let source = if bridge.name == DEFAULT_NAME {
+        format!("{} '{}'", mirror.kind(), mirror.name())
+    } else {
+        format!("{} '{}' on {}", mirror.kind(), mirror.name(), bridge.name)
+    };
+
+    let reverse = if mirror.follows_nanoleaf() {
+        ", and changes made on them will be sent back"
+    } else {
+        ""
+    };
+
+    format!(
+        "The {} will be mirrored to the Nanoleaf {}{}.",
+        source,
+        outputs.join(", "),
+        reverse
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Reply, StandIn};
+
+    const LIGHTS: &str = include_str!("../tests/fixtures/hue/lights.json");
+    const ROOMS: &str = include_str!("../tests/fixtures/hue/rooms.json");
+    const ZONES: &str = include_str!("../tests/fixtures/hue/zones.json");
+    const GROUPED_LIGHTS: &str = include_str!("../tests/fixtures/hue/grouped_lights.json");
+    const ENTERTAINMENT_CONFIGURATIONS: &str =
+        include_str!("../tests/fixtures/hue/entertainment_configuration.json");
+    const ENTERTAINMENT: &str = include_str!("../tests/fixtures/hue/entertainment.json");
+
+    /// A stand-in bridge serving the recorded resources, and what was loaded from it.
+    async fn load() -> (StandIn, Hue, ResourceCache) {
+        let bridge = StandIn::start(|request| {
+            let body = match request.path.as_str() {
+                "/clip/v2/resource/light" => LIGHTS,
+                "/clip/v2/resource/room" => ROOMS,
+                "/clip/v2/resource/zone" => ZONES,
+                "/clip/v2/resource/grouped_light" => GROUPED_LIGHTS,
+                "/clip/v2/resource/entertainment_configuration" => ENTERTAINMENT_CONFIGURATIONS,
+                "/clip/v2/resource/entertainment" => ENTERTAINMENT,
+                _ => r#"{"errors":[],"data":[]}"#,
+            };
+            Reply::Json(200, body.to_string())
+        })
+        .await;
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+        let cache = ResourceCache::load(&hue).await.unwrap();
+
+        (bridge, hue, cache)
+    }
+
+    fn mapping(yaml: &str) -> MappingConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[tokio::test]
+    async fn checks_the_config_before_connecting() {
+        let config: NanoHueConfig = serde_yaml::from_str(
+            "
+hue: {host: 127.0.0.1, username: abc}
+nanoleaf_devices:
+  shelf: {host: 127.0.0.1, token: def}
+mappings:
+  - room: Kitchen
+    nanoleaf: [shelf]
+  - room: Office
+    nanoleaf: [shelf]
+",
+        )
+        .unwrap();
+
+        let err = connect(&config, true).await.err().unwrap();
+        assert_eq!(err.0.len(), 1);
+        assert!(err.0[0].starts_with("mappings[1].nanoleaf uses device 'shelf'"));
+    }
+
+    #[tokio::test]
+    async fn checks_every_bridge() {
+        // Nothing listens on these ports, so every device is unreachable.
+        let config: NanoHueConfig = serde_yaml::from_str(
+            "
+hue: {host: '127.0.0.1:1', username: abc}
+bridges:
+  upstairs: {host: '127.0.0.1:2', username: def}
+nanoleaf_devices:
+  landing: {host: 127.0.0.1, token: ghi}
+  lounge: {host: 127.0.0.1, token: jkl}
+  office: {host: 127.0.0.1, token: mno}
+mappings:
+  - room: Lounge
+    nanoleaf: [lounge]
+  - room: Landing
+    bridge: upstairs
+    nanoleaf: [landing]
+  - room: Office
+    nanoleaf: [office]
+",
+        )
+        .unwrap();
+
+        let err = connect(&config, true).await.err().unwrap();
+
+        // Each bridge is only contacted once, however many mappings use it.
+        let keys: Vec<&str> = err
+            .0
+            .iter()
+            .filter_map(|problem| problem.split(':').next())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "hue",
+                "bridges.upstairs",
+                "nanoleaf_devices.landing",
+                "nanoleaf_devices.lounge",
+                "nanoleaf_devices.office"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_the_rooms_when_one_is_not_found() {
+        let (_bridge, hue, cache) = load().await;
+
+        let err = check_source(&hue, &cache, &mapping("{room: Livng, nanoleaf: [default]}"))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            err,
+            "room 'Livng' not found; available rooms: Living room, Hallway"
+        );
+    }
+
+    #[tokio::test]
+    async fn finds_zones_and_single_lights() {
+        let (_bridge, hue, cache) = load().await;
+
+        let zone = check_source(&hue, &cache, &mapping("{zone: TV, nanoleaf: [default]}"))
+            .await
+            .unwrap();
+        match zone {
+            (
+                Source::Group {
+                    kind,
+                    group,
+                    grouped_light,
+                },
+                None,
+            ) => {
+                assert_eq!(kind, SourceKind::Zone);
+                assert_eq!(group.metadata.name, "TV");
+                assert_eq!(grouped_light, "b1d3f5a7-2c4e-4a6b-8d0f-3e5a7c9b1d42");
+                assert!(cache
+                    .lights()
+                    .iter()
+                    .filter(|light| group.contains_light(light))
+                    .map(|light| light.metadata.name.as_str())
+                    .eq(["Play bar left", "Desk"]));
+            }
+            _ => panic!("expected the TV zone"),
+        }
+
+        let light = check_source(&hue, &cache, &mapping("{light: Desk, nanoleaf: [default]}"))
+            .await
+            .unwrap();
+        match light {
+            (Source::Light(light), None) => {
+                assert_eq!(light.id, "8f0d1c5e-51a9-4c1e-a3f2-2f61d2b0c6a4")
+            }
+            _ => panic!("expected the Desk light"),
+        }
+
+        // A zone isn't looked for among the rooms, or the other way around.
+        let err = check_source(&hue, &cache, &mapping("{room: TV, nanoleaf: [default]}"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            "room 'TV' not found; available rooms: Living room, Hallway"
+        );
+
+        let err = check_source(&hue, &cache, &mapping("{light: Lamp, nanoleaf: [default]}"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            "light 'Lamp' not found; available lights: Play bar left, Desk, Hallway"
+        );
+    }
+
+    #[tokio::test]
+    async fn takes_positions_from_an_entertainment_area() {
+        let (_bridge, hue, cache) = load().await;
+
+        // The area's height is used for `y`.
+        let (id, positions) = check_entertainment(&hue, &cache, "TV area").await.unwrap();
+        assert_eq!(id, "c4e6a8b0-2d1f-4b3a-9c5e-7f0a2b4d6e83");
+        assert_eq!(positions.len(), 2);
+        assert_eq!(
+            positions[0],
+            (
+                String::from("3a6710fa-4474-4eba-b533-5e6e72968feb"),
+                Position { x: -0.8, y: -0.2 }
+            )
+        );
+        assert!((positions[1].1.x - 0.4).abs() < 1e-6 && (positions[1].1.y - 0.6).abs() < 1e-6);
+
+        // Positions outside the room are brought back to its edge.
+        let (_, positions) = check_entertainment(&hue, &cache, "Music").await.unwrap();
+        assert_eq!(positions[0].1, Position { x: 1.0, y: -1.0 });
+
+        let err = check_entertainment(&hue, &cache, "Movies")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            "entertainment area 'Movies' not found; available entertainment areas: TV area, Music"
+        );
+    }
+}
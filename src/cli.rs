@@ -0,0 +1,167 @@
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
+
+use crate::commands::output::OutputFormat;
+
+/// Mirror Philips Hue scenes to a Nanoleaf device.
+#[derive(Debug, Parser)]
+#[command(name = "nanohue", version)]
+pub struct Cli {
+    /// Path to the configuration file.
+    #[arg(long, global = true, default_value = "config.yml")]
+    pub config: String,
+
+    /// Minimum level of log messages to print (off, error, warn, info, debug, trace).
+    #[arg(long, global = true, default_value = "info")]
+    pub log_level: LevelFilter,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start mirroring the configured Hue rooms, zones and lights to the Nanoleaf devices.
+    Run {
+        /// Do everything except updating the Nanoleaf, logging the updates instead.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Validate the configuration file and check that every device can be reached.
+    CheckConfig,
+
+    /// Create an application key on a Hue bridge and store it in the config file.
+    PairHue {
+        /// IP address or hostname of the Hue bridge.
+        #[arg(long)]
+        host: String,
+
+        /// Store the key under this name in `bridges`, rather than in `hue`.
+        #[arg(long)]
+        name: Option<String>,
+
+        /// How long to wait for the link button to be pressed, in seconds.
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+    },
+
+    /// Obtain an auth token from a Nanoleaf controller and store it in the config file.
+    PairNanoleaf {
+        /// IP address or hostname of the Nanoleaf controller.
+        #[arg(long)]
+        host: String,
+
+        /// Store the token under this name in `nanoleaf_devices`, rather than in `nanoleaf`.
+        #[arg(long)]
+        name: Option<String>,
+
+        /// How long to wait for the controller to enter pairing mode, in seconds.
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+
+    /// Search the local network for Hue bridges and Nanoleaf controllers.
+    Discover {
+        /// How long to search for, in seconds.
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+
+        /// How to print the results.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+
+    /// Inspect the rooms, zones, lights and scenes on the Hue bridge.
+    Hue {
+        /// How to print the results.
+        #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+
+        /// Name of the bridge in `bridges` to inspect. Defaults to the `hue` section.
+        #[arg(long, global = true)]
+        bridge: Option<String>,
+
+        #[command(subcommand)]
+        command: HueCommand,
+    },
+
+    /// Inspect the Nanoleaf controller.
+    Nanoleaf {
+        /// How to print the results.
+        #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+
+        /// Name of the device in `nanoleaf_devices` to inspect. Defaults to the `nanoleaf` section.
+        #[arg(long, global = true)]
+        device: Option<String>,
+
+        #[command(subcommand)]
+        command: NanoleafCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HueCommand {
+    /// List the rooms on the bridge.
+    Rooms,
+
+    /// List the zones on the bridge.
+    Zones,
+
+    /// List the lights on the bridge, along with their current state.
+    Lights,
+
+    /// List the scenes on the bridge.
+    Scenes {
+        /// Only list scenes belonging to the room or zone with this name.
+        #[arg(long)]
+        room: Option<String>,
+    },
+
+    /// List the entertainment areas on the bridge, along with where their lights are.
+    Entertainment,
+
+    /// Recall a scene, e.g. to check how it's mirrored.
+    Recall {
+        /// The name of the scene.
+        scene: String,
+
+        /// The room or zone the scene belongs to, if several scenes share its name.
+        #[arg(long)]
+        room: Option<String>,
+
+        /// Cycle through the scene's palette rather than showing it as saved.
+        #[arg(long)]
+        dynamic: bool,
+
+        /// The brightness to recall the scene at, from 0 to 100, instead of its own.
+        #[arg(long)]
+        brightness: Option<f32>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NanoleafCommand {
+    /// Show the controller's model, firmware and serial number.
+    Info,
+
+    /// List the panels, along with their positions and orientation.
+    Layout,
+
+    /// List the effects stored on the controller.
+    Effects,
+
+    /// Show the controller's current state.
+    State,
+
+    /// Light up each panel in turn and print its ID and position.
+    Identify {
+        /// How long to light each panel for, in seconds.
+        #[arg(long, default_value_t = 2)]
+        duration: u64,
+    },
+
+    /// Print changes made on the controller as they happen, until interrupted.
+    Events,
+}
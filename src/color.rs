@@ -1,11 +1,9 @@
-use std::collections::HashSet;
-
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 pub struct ColorCoordinate {
-    x: f32,
-    y: f32,
+    pub x: f32,
+    pub y: f32,
 }
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
@@ -15,8 +13,26 @@ pub struct ColorGamut2 {
     blue: ColorCoordinate,
 }
 
+/// Gamut C, which covers the current range of Hue color lights. Used for
+/// colors that aren't tied to a particular light, such as a scene's palette.
+impl Default for ColorGamut2 {
+    fn default() -> Self {
+        ColorGamut2 {
+            red: ColorCoordinate {
+                x: 0.6915,
+                y: 0.3083,
+            },
+            green: ColorCoordinate { x: 0.17, y: 0.7 },
+            blue: ColorCoordinate {
+                x: 0.1532,
+                y: 0.0475,
+            },
+        }
+    }
+}
+
 impl ColorGamut2 {
-    fn to_array(&self) -> ColorGamut {
+    fn to_array(self) -> ColorGamut {
         [
             [self.red.x, self.red.y],
             [self.green.x, self.green.y],
@@ -32,6 +48,33 @@ pub struct HSVColor {
     brightness: u8,
 }
 
+impl HSVColor {
+    pub fn to_rgb(self) -> RGBColor {
+        let s = self.saturation.min(100) as f32 / 100_f32;
+        let v = self.brightness.min(100) as f32 / 100_f32;
+        let h = (self.hue % 360) as f32 / 60_f32;
+
+        let c = v * s;
+        let x = c * (1_f32 - (h % 2_f32 - 1_f32).abs());
+        let m = v - c;
+
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0_f32),
+            1 => (x, c, 0_f32),
+            2 => (0_f32, c, x),
+            3 => (0_f32, x, c),
+            4 => (x, 0_f32, c),
+            _ => (c, 0_f32, x),
+        };
+
+        RGBColor {
+            red: ((r + m) * 255.0).round() as u8,
+            green: ((g + m) * 255.0).round() as u8,
+            blue: ((b + m) * 255.0).round() as u8,
+        }
+    }
+}
+
 fn gamma_correction(x: f32) -> f32 {
     if x <= 0.0031308 {
         return 12.92 * x;
@@ -67,13 +110,18 @@ fn min(values: Vec<f32>) -> f32 {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct RGBColor {
-    red: u8,
-    green: u8,
-    blue: u8,
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
 }
 
+/// The `brightness` at which `RGBColor::from_coordinate` gives a color at
+/// its full intensity, leaving the light's own brightness to be applied
+/// separately.
+pub const FULL_BRIGHTNESS: f32 = 255.0;
+
 impl RGBColor {
     pub fn from_coordinate(
         color: ColorCoordinate,
@@ -96,7 +144,7 @@ impl RGBColor {
         // Convert to RGB using Wide RGB D65 conversion
         let mut r = x * 1.656492 - y * 0.354851 - z * 0.255038;
         let mut g = -x * 0.707196 + y * 1.655397 + z * 0.036152;
-        let mut b = x * 0.051713 - y * 0.121364 + z * 1.011530;
+        let mut b = x * 0.051713 - y * 0.121364 + z * 1.01153;
 
         // Apply reverse gamma correction
         r = gamma_correction(r);
@@ -123,9 +171,9 @@ impl RGBColor {
 
         // If one component is greater than 1, weight components by that value.
         if max_component > 1.0 {
-            r = r / max_component;
-            g = g / max_component;
-            b = b / max_component;
+            r /= max_component;
+            g /= max_component;
+            b /= max_component;
         }
 
         RGBColor {
@@ -135,7 +183,34 @@ impl RGBColor {
         }
     }
 
-    pub fn to_hsv(&self) -> HSVColor {
+    /// The inverse of `from_coordinate`. The bridge fits the coordinates into
+    /// each light's gamut, so they aren't clamped here.
+    pub fn to_coordinate(self) -> ColorCoordinate {
+        let r = inverse_gamma_correction(self.red as f32 / 255_f32);
+        let g = inverse_gamma_correction(self.green as f32 / 255_f32);
+        let b = inverse_gamma_correction(self.blue as f32 / 255_f32);
+
+        // Convert to XYZ using the Wide RGB D65 conversion
+        let x = r * 0.664511 + g * 0.154324 + b * 0.162028;
+        let y = r * 0.283881 + g * 0.668433 + b * 0.047685;
+        let z = r * 0.000088 + g * 0.07231 + b * 0.986039;
+
+        let total = x + y + z;
+        if total <= 0.0 {
+            // Black has no chromaticity, so fall back to the D65 white point.
+            return ColorCoordinate {
+                x: 0.3127,
+                y: 0.329,
+            };
+        }
+
+        ColorCoordinate {
+            x: x / total,
+            y: y / total,
+        }
+    }
+
+    pub fn to_hsv(self) -> HSVColor {
         // R, G, B values are divided by 255
         // to change the range from 0..255 to 0..1:
         let r = self.red as f32 / 255_f32;
@@ -166,13 +241,12 @@ impl RGBColor {
             h = (60_f32 * ((r - g) / diff) + 240_f32) % 360_f32;
         }
 
-        let s: f32;
         // if cmax equal zero
-        if cmax == 0_f32 {
-            s = 0_f32;
+        let s = if cmax == 0_f32 {
+            0_f32
         } else {
-            s = (diff / cmax) * 100_f32;
-        }
+            (diff / cmax) * 100_f32
+        };
 
         // compute v
         let v = cmax * 100_f32;
@@ -184,13 +258,308 @@ impl RGBColor {
     }
 }
 
+/// Convert a Hue color temperature in mirek (micro reciprocal degrees) to Kelvin.
+pub fn mirek_to_kelvin(mirek: u32) -> u32 {
+    1_000_000 / mirek.max(1)
+}
+
+impl RGBColor {
+    /// The color of a blackbody at the given temperature, using Tanner
+    /// Helland's approximation. Temperatures are clamped to 1000K..40000K,
+    /// where the approximation holds.
+    pub fn from_kelvin(kelvin: u32) -> RGBColor {
+        let temperature = kelvin.clamp(1000, 40000) as f32 / 100.0;
+
+        let red = if temperature <= 66.0 {
+            255.0
+        } else {
+            329.69873 * (temperature - 60.0).powf(-0.13320476)
+        };
+
+        let green = if temperature <= 66.0 {
+            99.4708 * temperature.ln() - 161.11957
+        } else {
+            288.12216 * (temperature - 60.0).powf(-0.07551485)
+        };
+
+        let blue = if temperature >= 66.0 {
+            255.0
+        } else if temperature <= 19.0 {
+            0.0
+        } else {
+            138.51773 * (temperature - 10.0).ln() - 305.0448
+        };
+
+        RGBColor {
+            red: red.round().clamp(0.0, 255.0) as u8,
+            green: green.round().clamp(0.0, 255.0) as u8,
+            blue: blue.round().clamp(0.0, 255.0) as u8,
+        }
+    }
+}
+
+/// A color in CIELAB, relative to the D65 white point. Distances in Lab are
+/// much closer to how different colors look than distances in RGB or HSV.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LabColor {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+/// CIELAB in cylindrical form: lightness, chroma, and hue in degrees.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LChColor {
+    pub l: f32,
+    pub c: f32,
+    pub h: f32,
+}
+
+// The D65 reference white, in XYZ.
+const WHITE_X: f32 = 0.95047;
+const WHITE_Y: f32 = 1.0;
+const WHITE_Z: f32 = 1.08883;
+
+const LAB_DELTA: f32 = 6.0 / 29.0;
+
+fn inverse_gamma_correction(x: f32) -> f32 {
+    if x <= 0.04045 {
+        return x / 12.92;
+    }
+
+    f32::powf((x + 0.055) / 1.055, 2.4)
+}
+
+fn lab_f(t: f32) -> f32 {
+    if t > LAB_DELTA.powi(3) {
+        t.cbrt()
+    } else {
+        t / (3.0 * LAB_DELTA.powi(2)) + 4.0 / 29.0
+    }
+}
+
+fn lab_f_inverse(t: f32) -> f32 {
+    if t > LAB_DELTA {
+        t.powi(3)
+    } else {
+        3.0 * LAB_DELTA.powi(2) * (t - 4.0 / 29.0)
+    }
+}
+
+impl RGBColor {
+    pub fn to_lab(self) -> LabColor {
+        let r = inverse_gamma_correction(self.red as f32 / 255.0);
+        let g = inverse_gamma_correction(self.green as f32 / 255.0);
+        let b = inverse_gamma_correction(self.blue as f32 / 255.0);
+
+        // sRGB to XYZ
+        let x = r * 0.4124564 + g * 0.3575761 + b * 0.1804375;
+        let y = r * 0.2126729 + g * 0.7151522 + b * 0.072175;
+        let z = r * 0.0193339 + g * 0.119192 + b * 0.9503041;
+
+        let fx = lab_f(x / WHITE_X);
+        let fy = lab_f(y / WHITE_Y);
+        let fz = lab_f(z / WHITE_Z);
+
+        LabColor {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+}
+
+impl LabColor {
+    /// Convert back to sRGB. Colors outside of sRGB are clamped.
+    pub fn to_rgb(self) -> RGBColor {
+        let fy = (self.l + 16.0) / 116.0;
+        let fx = fy + self.a / 500.0;
+        let fz = fy - self.b / 200.0;
+
+        let x = lab_f_inverse(fx) * WHITE_X;
+        let y = lab_f_inverse(fy) * WHITE_Y;
+        let z = lab_f_inverse(fz) * WHITE_Z;
+
+        // XYZ to sRGB
+        let r = x * 3.2404542 - y * 1.5371385 - z * 0.4985314;
+        let g = -x * 0.969266 + y * 1.8760108 + z * 0.041556;
+        let b = x * 0.0556434 - y * 0.2040259 + z * 1.0572252;
+
+        let channel = |value: f32| {
+            (gamma_correction(value.max(0.0)) * 255.0)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        };
+
+        RGBColor {
+            red: channel(r),
+            green: channel(g),
+            blue: channel(b),
+        }
+    }
+
+    pub fn to_lch(self) -> LChColor {
+        LChColor {
+            l: self.l,
+            c: self.a.hypot(self.b),
+            h: self.b.atan2(self.a).to_degrees().rem_euclid(360.0),
+        }
+    }
+}
+
+impl LChColor {
+    #[allow(unused)]
+    pub fn to_lab(self) -> LabColor {
+        let (sin, cos) = self.h.to_radians().sin_cos();
+
+        LabColor {
+            l: self.l,
+            a: self.c * cos,
+            b: self.c * sin,
+        }
+    }
+}
+
+/// The CIEDE2000 color difference between two colors. A difference below 1 is
+/// imperceptible, and around 2 is the smallest most people notice side by side.
+pub fn delta_e_2000(one: LabColor, two: LabColor) -> f32 {
+    let pow7 = |value: f32| value.powi(7);
+    let twenty_five_7 = pow7(25.0);
+
+    let c_bar = (one.to_lch().c + two.to_lch().c) / 2.0;
+    let g = 0.5 * (1.0 - (pow7(c_bar) / (pow7(c_bar) + twenty_five_7)).sqrt());
+
+    let prime = |color: LabColor| {
+        LabColor {
+            a: color.a * (1.0 + g),
+            ..color
+        }
+        .to_lch()
+    };
+    let one = prime(one);
+    let two = prime(two);
+
+    let delta_l = two.l - one.l;
+    let delta_c = two.c - one.c;
+
+    // Hue is undefined for greys, so it doesn't contribute.
+    let has_hue = one.c * two.c != 0.0;
+    let delta_h = if !has_hue {
+        0.0
+    } else if (two.h - one.h).abs() <= 180.0 {
+        two.h - one.h
+    } else if two.h > one.h {
+        two.h - one.h - 360.0
+    } else {
+        two.h - one.h + 360.0
+    };
+    let delta_h = 2.0 * (one.c * two.c).sqrt() * (delta_h.to_radians() / 2.0).sin();
+
+    let l_bar = (one.l + two.l) / 2.0;
+    let c_bar = (one.c + two.c) / 2.0;
+    let h_bar = if !has_hue {
+        one.h + two.h
+    } else if (one.h - two.h).abs() <= 180.0 {
+        (one.h + two.h) / 2.0
+    } else if one.h + two.h < 360.0 {
+        (one.h + two.h + 360.0) / 2.0
+    } else {
+        (one.h + two.h - 360.0) / 2.0
+    };
+
+    let cos = |degrees: f32| degrees.to_radians().cos();
+    let t =
+        1.0 - 0.17 * cos(h_bar - 30.0) + 0.24 * cos(2.0 * h_bar) + 0.32 * cos(3.0 * h_bar + 6.0)
+            - 0.2 * cos(4.0 * h_bar - 63.0);
+
+    let delta_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (pow7(c_bar) / (pow7(c_bar) + twenty_five_7)).sqrt();
+    let s_l = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let l = delta_l / s_l;
+    let c = delta_c / s_c;
+    let h = delta_h / s_h;
+
+    (l * l + c * c + h * h + r_t * c * h).max(0.0).sqrt()
+}
+
+/// Merge colors that look alike, then keep merging the closest pair until
+/// there are at most `max_colors`. Merged colors are averaged in Lab, weighted
+/// by how much each contributed, and their weights are added together. The
+/// result is ordered from the heaviest to the lightest color.
+///
+/// Every merge compares each pair of colors, so this is O(n³) in the number of
+/// colors. That's fine for the lights of a room or the actions of a scene,
+/// which number in the tens, but it shouldn't be given per-pixel input.
+fn reduce_palette(
+    colors: &[(RGBColor, f32)],
+    merge_threshold: f32,
+    max_colors: usize,
+) -> Vec<(RGBColor, f32)> {
+    let mut clusters: Vec<(LabColor, f32)> = colors
+        .iter()
+        .map(|(color, weight)| (color.to_lab(), weight.max(0.0)))
+        .collect();
+
+    loop {
+        let mut closest: Option<(usize, usize, f32)> = None;
+        for i in 0..clusters.len() {
+            for j in (i + 1)..clusters.len() {
+                let distance = delta_e_2000(clusters[i].0, clusters[j].0);
+                if closest.is_none_or(|(_, _, best)| distance < best) {
+                    closest = Some((i, j, distance));
+                }
+            }
+        }
+
+        let Some((i, j, distance)) = closest else {
+            break;
+        };
+
+        if distance >= merge_threshold && clusters.len() <= max_colors {
+            break;
+        }
+
+        let (two, two_weight) = clusters.remove(j);
+        let (one, one_weight) = clusters[i];
+        let total = one_weight + two_weight;
+        let mix = |one: f32, two: f32| {
+            if total > 0.0 {
+                (one * one_weight + two * two_weight) / total
+            } else {
+                (one + two) / 2.0
+            }
+        };
+
+        clusters[i] = (
+            LabColor {
+                l: mix(one.l, two.l),
+                a: mix(one.a, two.a),
+                b: mix(one.b, two.b),
+            },
+            total,
+        );
+    }
+
+    // A stable sort, so equally weighted colors keep the order they came in.
+    clusters.sort_by(|one, two| two.1.total_cmp(&one.1));
+
+    clusters
+        .into_iter()
+        .map(|(color, weight)| (color.to_rgb(), weight))
+        .collect()
+}
+
 pub type ColorGamut = [[f32; 2]; 3];
 
 // struct Palette {}
 
 fn cross_product(point1: ColorCoordinate, point2: ColorCoordinate) -> f32 {
     // Returns the cross product of two XYPoints.
-    return point1.x * point2.y - point1.y * point2.x;
+    point1.x * point2.y - point1.y * point2.x
 }
 
 fn check_point_in_lamps_reach(color: ColorCoordinate, gamut: ColorGamut) -> bool {
@@ -212,7 +581,7 @@ fn check_point_in_lamps_reach(color: ColorCoordinate, gamut: ColorGamut) -> bool
     let s = cross_product(q, v2) / cross_product(v1, v2);
     let t = cross_product(v1, q) / cross_product(v1, v2);
 
-    return (s >= 0.0) && (t >= 0.0) && (s + t <= 1.0);
+    (s >= 0.0) && (t >= 0.0) && (s + t <= 1.0)
 }
 
 fn get_closest_point_to_line(
@@ -231,13 +600,7 @@ fn get_closest_point_to_line(
     };
     let ab2 = ab.x * ab.x + ab.y * ab.y;
     let ap_ab = ap.x * ab.x + ap.y * ab.y;
-    let mut t: f32 = ap_ab / ab2;
-
-    if t < 0.0 {
-        t = 0.0
-    } else if t > 1.0 {
-        t = 1.0
-    }
+    let t: f32 = (ap_ab / ab2).clamp(0.0, 1.0);
 
     ColorCoordinate {
         x: a.x + ab.x * t,
@@ -313,4 +676,342 @@ fn get_closest_point_to_point(gamut: ColorGamut, xy_point: ColorCoordinate) -> C
     ColorCoordinate { x: cx, y: cy }
 }
 
-pub type Palette = HashSet<HSVColor>;
+/// A palette color, along with how much of the room it covers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PaletteEntry {
+    pub color: HSVColor,
+    pub weight: f32,
+}
+
+/// The colors of a room, ordered from the one covering the most of it to the
+/// least. Colors with the same weight keep the order of the lights or scene
+/// actions they came from, so the same scene always gives the same palette.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Palette {
+    entries: Vec<PaletteEntry>,
+}
+
+impl Palette {
+    /// Build a palette from each light's color and weight, merging colors
+    /// that look alike. See `reduce_palette`.
+    pub fn new(colors: &[(RGBColor, f32)], merge_threshold: f32, max_colors: usize) -> Palette {
+        Palette {
+            entries: reduce_palette(colors, merge_threshold, max_colors)
+                .into_iter()
+                .map(|(color, weight)| PaletteEntry {
+                    color: color.to_hsv(),
+                    weight,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn single(color: HSVColor) -> Palette {
+        Palette {
+            entries: vec![PaletteEntry { color, weight: 1.0 }],
+        }
+    }
+
+    /// Each color along with its share of the palette's weight, as a
+    /// percentage. If nothing has any weight, the colors are shared equally.
+    pub fn probabilities(&self) -> Vec<(HSVColor, f32)> {
+        let total: f32 = self.entries.iter().map(|entry| entry.weight).sum();
+
+        self.entries
+            .iter()
+            .map(|entry| {
+                let probability = if total > 0.0 {
+                    entry.weight / total * 100.0
+                } else {
+                    100.0 / self.entries.len() as f32
+                };
+
+                (entry.color, probability)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Lab = (f32, f32, f32);
+
+    fn lab(l: f32, a: f32, b: f32) -> LabColor {
+        LabColor { l, a, b }
+    }
+
+    fn rgb(red: u8, green: u8, blue: u8) -> RGBColor {
+        RGBColor { red, green, blue }
+    }
+
+    /// Test data from Sharma, Wu and Dalal, "The CIEDE2000 Color-Difference
+    /// Formula: Implementation Notes, Supplementary Test Data, and
+    /// Mathematical Observations" (2005), table 1.
+    const SHARMA_PAIRS: [(Lab, Lab, f32); 34] = [
+        ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+        ((50.0, 3.1571, -77.2803), (50.0, 0.0, -82.7485), 2.8615),
+        ((50.0, 2.8361, -74.02), (50.0, 0.0, -82.7485), 3.4412),
+        ((50.0, -1.3802, -84.2814), (50.0, 0.0, -82.7485), 1.0),
+        ((50.0, -1.1848, -84.8006), (50.0, 0.0, -82.7485), 1.0),
+        ((50.0, -0.9009, -85.5211), (50.0, 0.0, -82.7485), 1.0),
+        ((50.0, 0.0, 0.0), (50.0, -1.0, 2.0), 2.3669),
+        ((50.0, -1.0, 2.0), (50.0, 0.0, 0.0), 2.3669),
+        ((50.0, 2.49, -0.001), (50.0, -2.49, 0.0009), 7.1792),
+        ((50.0, 2.49, -0.001), (50.0, -2.49, 0.001), 7.1792),
+        ((50.0, 2.49, -0.001), (50.0, -2.49, 0.0011), 7.2195),
+        ((50.0, 2.49, -0.001), (50.0, -2.49, 0.0012), 7.2195),
+        ((50.0, -0.001, 2.49), (50.0, 0.0009, -2.49), 4.8045),
+        ((50.0, -0.001, 2.49), (50.0, 0.001, -2.49), 4.8045),
+        ((50.0, -0.001, 2.49), (50.0, 0.0011, -2.49), 4.7461),
+        ((50.0, 2.5, 0.0), (50.0, 0.0, -2.5), 4.3065),
+        ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+        ((50.0, 2.5, 0.0), (61.0, -5.0, 29.0), 22.8977),
+        ((50.0, 2.5, 0.0), (56.0, -27.0, -3.0), 31.903),
+        ((50.0, 2.5, 0.0), (58.0, 24.0, 15.0), 19.4535),
+        ((50.0, 2.5, 0.0), (50.0, 3.1736, 0.5854), 1.0),
+        ((50.0, 2.5, 0.0), (50.0, 3.2972, 0.0), 1.0),
+        ((50.0, 2.5, 0.0), (50.0, 1.8634, 0.5757), 1.0),
+        ((50.0, 2.5, 0.0), (50.0, 3.2592, 0.335), 1.0),
+        (
+            (60.2574, -34.0099, 36.2677),
+            (60.4626, -34.1751, 39.4387),
+            1.2644,
+        ),
+        (
+            (63.0109, -31.0961, -5.8663),
+            (62.8187, -29.7946, -4.0864),
+            1.263,
+        ),
+        ((61.2901, 3.7196, -5.3901), (61.4292, 2.248, -4.962), 1.8731),
+        (
+            (35.0831, -44.1164, 3.7933),
+            (35.0232, -40.0716, 1.5901),
+            1.8645,
+        ),
+        (
+            (22.7233, 20.0904, -46.694),
+            (23.0331, 14.973, -42.5619),
+            2.0373,
+        ),
+        (
+            (36.4612, 47.858, 18.3852),
+            (36.2715, 50.5065, 21.2231),
+            1.4146,
+        ),
+        (
+            (90.8027, -2.0831, 1.441),
+            (91.1528, -1.6435, 0.0447),
+            1.4441,
+        ),
+        (
+            (90.9257, -0.5406, -0.9208),
+            (88.6381, -0.8985, -0.7239),
+            1.5381,
+        ),
+        (
+            (6.7747, -0.2908, -2.4247),
+            (5.8714, -0.0985, -2.2286),
+            0.6377,
+        ),
+        ((2.0776, 0.0795, -1.135), (0.9033, -0.0636, -0.5514), 0.9082),
+    ];
+
+    #[test]
+    fn matches_the_ciede2000_reference_pairs() {
+        for (index, (one, two, expected)) in SHARMA_PAIRS.into_iter().enumerate() {
+            let one = lab(one.0, one.1, one.2);
+            let two = lab(two.0, two.1, two.2);
+
+            let difference = delta_e_2000(one, two);
+            assert!(
+                (difference - expected).abs() < 1e-3,
+                "pair {}: expected {}, got {}",
+                index + 1,
+                expected,
+                difference
+            );
+            // The difference is symmetric.
+            assert!((delta_e_2000(two, one) - difference).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn gives_full_intensity_colors_at_full_brightness() {
+        let gamut = ColorGamut2::default();
+
+        // The brightest channel is always at full intensity, whatever the hue.
+        for coordinate in [
+            gamut.red,
+            gamut.green,
+            gamut.blue,
+            ColorCoordinate { x: 0.55, y: 0.4 },
+            ColorCoordinate {
+                x: 0.3127,
+                y: 0.329,
+            },
+        ] {
+            let color = RGBColor::from_coordinate(coordinate, gamut, FULL_BRIGHTNESS);
+            assert_eq!(
+                color.red.max(color.green).max(color.blue),
+                255,
+                "{:?}",
+                coordinate
+            );
+        }
+    }
+
+    #[test]
+    fn converts_srgb_primaries_to_lab() {
+        let red = rgb(255, 0, 0).to_lab();
+        assert!((red.l - 53.24).abs() < 0.01);
+        assert!((red.a - 80.09).abs() < 0.01);
+        assert!((red.b - 67.20).abs() < 0.01);
+
+        let white = rgb(255, 255, 255).to_lab();
+        assert!((white.l - 100.0).abs() < 0.01);
+        assert!(white.a.abs() < 0.01 && white.b.abs() < 0.01);
+    }
+
+    #[test]
+    fn round_trips_between_rgb_and_lab() {
+        for red in (0..=255).step_by(15) {
+            for green in (0..=255).step_by(15) {
+                for blue in (0..=255).step_by(15) {
+                    let color = rgb(red, green, blue);
+                    assert_eq!(color.to_lab().to_rgb(), color);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_between_lab_and_lch() {
+        let color = lab(60.2574, -34.0099, 36.2677);
+        let back = color.to_lch().to_lab();
+
+        assert!((back.l - color.l).abs() < 1e-4);
+        assert!((back.a - color.a).abs() < 1e-4);
+        assert!((back.b - color.b).abs() < 1e-4);
+    }
+
+    #[test]
+    fn merges_near_duplicates_and_keeps_distinct_colors() {
+        let colors = [
+            (rgb(255, 0, 0), 1.0),
+            (rgb(0, 0, 255), 1.0),
+            (rgb(250, 4, 4), 1.0),
+            (rgb(0, 255, 0), 0.5),
+        ];
+
+        let reduced = reduce_palette(&colors, 5.0, 5);
+
+        assert_eq!(reduced.len(), 3);
+        // The two reds are merged, adding their weights together.
+        let (red, weight) = reduced[0];
+        assert_eq!(weight, 2.0);
+        assert!(delta_e_2000(red.to_lab(), rgb(255, 0, 0).to_lab()) < 5.0);
+        // The rest come through untouched, heaviest first.
+        assert_eq!(reduced[1], (rgb(0, 0, 255), 1.0));
+        assert_eq!(reduced[2], (rgb(0, 255, 0), 0.5));
+    }
+
+    #[test]
+    fn merges_the_closest_colors_down_to_the_limit() {
+        let colors = [
+            (rgb(255, 0, 0), 1.0),
+            (rgb(255, 128, 0), 1.0),
+            (rgb(0, 0, 255), 1.0),
+        ];
+
+        let reduced = reduce_palette(&colors, 0.0, 2);
+
+        assert_eq!(reduced.len(), 2);
+        assert_eq!(reduced[0].1, 2.0);
+        assert_eq!(reduced[1], (rgb(0, 0, 255), 1.0));
+    }
+
+    #[test]
+    fn keeps_the_order_of_equally_weighted_colors() {
+        let colors = [
+            (rgb(0, 255, 0), 1.0),
+            (rgb(255, 0, 0), 1.0),
+            (rgb(0, 0, 255), 1.0),
+        ];
+
+        let reduced = reduce_palette(&colors, 5.0, 5);
+
+        let order: Vec<RGBColor> = reduced.iter().map(|(color, _)| *color).collect();
+        assert_eq!(order, vec![rgb(0, 255, 0), rgb(255, 0, 0), rgb(0, 0, 255)]);
+    }
+
+    #[test]
+    fn shares_the_palette_by_weight() {
+        let colors = [
+            (rgb(0, 0, 255), 30.0),
+            (rgb(255, 0, 0), 60.0),
+            (rgb(0, 255, 0), 10.0),
+            (rgb(255, 255, 0), 30.0),
+        ];
+
+        let probabilities = Palette::new(&colors, 5.0, 8).probabilities();
+
+        // Heaviest first, with the tie left in the order it was given.
+        let order: Vec<RGBColor> = probabilities
+            .iter()
+            .map(|(color, _)| color.to_rgb())
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                rgb(255, 0, 0),
+                rgb(0, 0, 255),
+                rgb(255, 255, 0),
+                rgb(0, 255, 0)
+            ]
+        );
+
+        let shares: Vec<f32> = probabilities.iter().map(|(_, share)| *share).collect();
+        let expected = [46.153_847, 23.076_923, 23.076_923, 7.692_308];
+        for (share, expected) in shares.iter().zip(expected) {
+            assert!((share - expected).abs() < 1e-4, "{:?}", shares);
+        }
+        assert!((shares.iter().sum::<f32>() - 100.0).abs() < 1e-3);
+
+        // The same colors always come out the same way.
+        assert_eq!(Palette::new(&colors, 5.0, 8).probabilities(), probabilities);
+    }
+
+    #[test]
+    fn shares_the_palette_equally_without_any_weight() {
+        let colors = [(rgb(255, 0, 0), 0.0), (rgb(0, 0, 255), 0.0)];
+
+        let shares: Vec<f32> = Palette::new(&colors, 5.0, 8)
+            .probabilities()
+            .iter()
+            .map(|(_, share)| *share)
+            .collect();
+        assert_eq!(shares, vec![50.0, 50.0]);
+
+        assert!(Palette::new(&[], 5.0, 8).probabilities().is_empty());
+    }
+
+    #[test]
+    fn converts_hue_color_temperatures_to_kelvin() {
+        // The coolest and warmest whites Hue lights support.
+        assert_eq!(mirek_to_kelvin(153), 6535);
+        assert_eq!(mirek_to_kelvin(500), 2000);
+        assert_eq!(mirek_to_kelvin(0), 1_000_000);
+
+        assert_eq!(RGBColor::from_kelvin(6535), rgb(255, 255, 251));
+        assert_eq!(RGBColor::from_kelvin(2000), rgb(255, 137, 14));
+
+        // Outside of where the approximation holds, the nearest edge is used.
+        assert_eq!(RGBColor::from_kelvin(500), RGBColor::from_kelvin(1000));
+        assert_eq!(
+            RGBColor::from_kelvin(mirek_to_kelvin(0)),
+            RGBColor::from_kelvin(40000)
+        );
+    }
+}
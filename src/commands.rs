@@ -0,0 +1,6 @@
+pub mod check;
+pub mod discover;
+pub mod hue;
+pub mod nanoleaf;
+pub mod output;
+pub mod pair;
@@ -0,0 +1,15 @@
+use crate::bridge::{connect, describe_mirror};
+
+pub async fn check_config(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let config = crate::config::read_config(config_path)?;
+    let connection = connect(&config, false).await?;
+
+    println!("{} is valid.", config_path);
+    for bridge in &connection.bridges {
+        for mirror in &bridge.mirrors {
+            println!("{}", describe_mirror(bridge, mirror));
+        }
+    }
+
+    Ok(())
+}
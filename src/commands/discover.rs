@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use crate::discovery::{discover, DeviceKind};
+
+use super::output::{print_rows, OutputFormat};
+
+pub async fn discover_devices(
+    timeout: Duration,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    eprintln!(
+        "Searching for Hue bridges and Nanoleaf controllers for {} seconds...",
+        timeout.as_secs()
+    );
+
+    let devices = discover(&[DeviceKind::HueBridge, DeviceKind::Nanoleaf], timeout).await;
+
+    print_rows(
+        format,
+        vec!["KIND", "ID", "HOST", "MODEL", "NAME"],
+        &devices,
+        |device| {
+            let kind = match device.kind {
+                DeviceKind::HueBridge => "hue",
+                DeviceKind::Nanoleaf => "nanoleaf",
+            };
+
+            vec![
+                kind.to_string(),
+                device.id.clone(),
+                device.host.clone(),
+                device.model.clone().unwrap_or_default(),
+                device.name.clone(),
+            ]
+        },
+    )
+}
@@ -0,0 +1,476 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use crate::{
+    bridge::connect_hue,
+    color::ColorCoordinate,
+    config::{read_section, HueConfig},
+    hue::{
+        client::Hue,
+        types::{Dimming, EntertainmentPosition, RecallAction, Room, SceneRecall},
+    },
+};
+
+use super::output::{print_rows, OutputFormat};
+
+#[derive(Debug, Serialize)]
+struct RoomSummary {
+    id: String,
+    name: String,
+    grouped_light: Option<String>,
+    devices: usize,
+    on: Option<bool>,
+    brightness: Option<f32>,
+}
+
+#[derive(Debug, Serialize)]
+struct ZoneSummary {
+    id: String,
+    name: String,
+    grouped_light: Option<String>,
+    lights: usize,
+    on: Option<bool>,
+    brightness: Option<f32>,
+}
+
+#[derive(Debug, Serialize)]
+struct LightSummary {
+    id: String,
+    name: String,
+    room: Option<String>,
+    gamut_type: Option<String>,
+    on: bool,
+    brightness: Option<f32>,
+    color: Option<ColorCoordinate>,
+    mirek: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct SceneSummary {
+    id: String,
+    name: String,
+    room: Option<String>,
+    status: String,
+    lights: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct EntertainmentLight {
+    id: String,
+    name: Option<String>,
+    position: EntertainmentPosition,
+}
+
+#[derive(Debug, Serialize)]
+struct EntertainmentSummary {
+    id: String,
+    name: String,
+    configuration_type: String,
+    status: String,
+    lights: Vec<EntertainmentLight>,
+}
+
+/// Connect to the bridge in the `hue` section, or the named one in `bridges`.
+async fn connect(
+    config_path: &str,
+    bridge: Option<&str>,
+) -> Result<Hue, Box<dyn std::error::Error>> {
+    let config: HueConfig = match bridge {
+        Some(name) => {
+            let mut bridges: BTreeMap<String, HueConfig> = read_section(config_path, "bridges")?;
+            bridges
+                .remove(name)
+                .ok_or_else(|| format!("no bridge named {:?} in bridges", name))?
+        }
+        None => read_section(config_path, "hue")?,
+    };
+
+    connect_hue(&config).await
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| String::from("-"), |value| value.to_string())
+}
+
+/// Find the ID of a room or zone by name, listing them all if there's no match.
+fn find_room_id(rooms: &[Room], name: &str) -> Result<String, String> {
+    match rooms.iter().find(|room| room.metadata.name == name) {
+        Some(room) => Ok(room.id.clone()),
+        None => {
+            let available: Vec<&str> = rooms
+                .iter()
+                .map(|room| room.metadata.name.as_str())
+                .collect();
+            Err(format!(
+                "room or zone {:?} not found; available rooms and zones: {}",
+                name,
+                available.join(", ")
+            ))
+        }
+    }
+}
+
+fn grouped_light_id(room: &Room) -> Option<String> {
+    room.services
+        .iter()
+        .find(|service| service.resource_type == "grouped_light")
+        .map(|service| service.id.clone())
+}
+
+pub async fn rooms(
+    config_path: &str,
+    bridge: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let hue_client = connect(config_path, bridge).await?;
+
+    let groups: HashMap<String, _> = hue_client
+        .groups()
+        .await?
+        .into_iter()
+        .map(|group| (group.id.clone(), group))
+        .collect();
+
+    let rooms: Vec<RoomSummary> = hue_client
+        .rooms()
+        .await?
+        .into_iter()
+        .map(|room| {
+            let grouped_light = grouped_light_id(&room);
+            let group = grouped_light.as_ref().and_then(|id| groups.get(id));
+
+            RoomSummary {
+                id: room.id.clone(),
+                name: room.metadata.name.clone(),
+                devices: room.children.len(),
+                on: group.map(|group| group.on.on),
+                brightness: group
+                    .and_then(|group| group.dimming.as_ref())
+                    .map(|dimming| dimming.brightness),
+                grouped_light,
+            }
+        })
+        .collect();
+
+    print_rows(
+        format,
+        vec!["NAME", "ID", "GROUPED LIGHT", "DEVICES", "ON", "BRIGHTNESS"],
+        &rooms,
+        |room| {
+            vec![
+                room.name.clone(),
+                room.id.clone(),
+                optional(&room.grouped_light),
+                room.devices.to_string(),
+                optional(&room.on),
+                optional(&room.brightness),
+            ]
+        },
+    )
+}
+
+pub async fn zones(
+    config_path: &str,
+    bridge: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let hue_client = connect(config_path, bridge).await?;
+
+    let groups: HashMap<String, _> = hue_client
+        .groups()
+        .await?
+        .into_iter()
+        .map(|group| (group.id.clone(), group))
+        .collect();
+
+    let zones: Vec<ZoneSummary> = hue_client
+        .zones()
+        .await?
+        .into_iter()
+        .map(|zone| {
+            let grouped_light = grouped_light_id(&zone);
+            let group = grouped_light.as_ref().and_then(|id| groups.get(id));
+
+            ZoneSummary {
+                id: zone.id.clone(),
+                name: zone.metadata.name.clone(),
+                lights: zone.children.len(),
+                on: group.map(|group| group.on.on),
+                brightness: group
+                    .and_then(|group| group.dimming.as_ref())
+                    .map(|dimming| dimming.brightness),
+                grouped_light,
+            }
+        })
+        .collect();
+
+    print_rows(
+        format,
+        vec!["NAME", "ID", "GROUPED LIGHT", "LIGHTS", "ON", "BRIGHTNESS"],
+        &zones,
+        |zone| {
+            vec![
+                zone.name.clone(),
+                zone.id.clone(),
+                optional(&zone.grouped_light),
+                zone.lights.to_string(),
+                optional(&zone.on),
+                optional(&zone.brightness),
+            ]
+        },
+    )
+}
+
+pub async fn lights(
+    config_path: &str,
+    bridge: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let hue_client = connect(config_path, bridge).await?;
+
+    // Lights belong to devices, and devices belong to rooms.
+    let device_rooms: HashMap<String, String> = hue_client
+        .rooms()
+        .await?
+        .into_iter()
+        .flat_map(|room| {
+            let name = room.metadata.name;
+            room.children
+                .into_iter()
+                .filter(|child| child.resource_type == "device")
+                .map(move |child| (child.id, name.clone()))
+        })
+        .collect();
+
+    let lights: Vec<LightSummary> = hue_client
+        .lights()
+        .await?
+        .into_iter()
+        .map(|light| LightSummary {
+            room: device_rooms.get(&light.owner.id).cloned(),
+            gamut_type: light
+                .color
+                .as_ref()
+                .and_then(|color| color.gamut_type.clone()),
+            color: light.color.as_ref().map(|color| color.xy),
+            mirek: light
+                .color_temperature
+                .as_ref()
+                .filter(|color_temperature| color_temperature.mirek_valid)
+                .and_then(|color_temperature| color_temperature.mirek),
+            brightness: light.dimming.as_ref().map(|dimming| dimming.brightness),
+            id: light.id,
+            name: light.metadata.name,
+            on: light.on.on,
+        })
+        .collect();
+
+    print_rows(
+        format,
+        vec![
+            "NAME",
+            "ID",
+            "ROOM",
+            "GAMUT",
+            "ON",
+            "BRIGHTNESS",
+            "COLOR",
+            "MIREK",
+        ],
+        &lights,
+        |light| {
+            vec![
+                light.name.clone(),
+                light.id.clone(),
+                optional(&light.room),
+                optional(&light.gamut_type),
+                light.on.to_string(),
+                optional(&light.brightness),
+                light.color.map_or_else(
+                    || String::from("-"),
+                    |xy| format!("{:.4},{:.4}", xy.x, xy.y),
+                ),
+                optional(&light.mirek),
+            ]
+        },
+    )
+}
+
+pub async fn scenes(
+    config_path: &str,
+    bridge: Option<&str>,
+    room: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let hue_client = connect(config_path, bridge).await?;
+
+    // Scenes can belong to either a room or a zone.
+    let mut rooms = hue_client.rooms().await?;
+    rooms.extend(hue_client.zones().await?);
+
+    let room_id = room.map(|name| find_room_id(&rooms, name)).transpose()?;
+
+    let room_names: HashMap<String, String> = rooms
+        .into_iter()
+        .map(|room| (room.id, room.metadata.name))
+        .collect();
+
+    let scenes: Vec<SceneSummary> = hue_client
+        .scenes()
+        .await?
+        .into_iter()
+        .filter(|scene| room_id.is_none() || room_id.as_ref() == Some(&scene.group.id))
+        .map(|scene| SceneSummary {
+            room: room_names.get(&scene.group.id).cloned(),
+            lights: scene.actions.len(),
+            id: scene.id,
+            name: scene.metadata.name,
+            status: scene.status.active,
+        })
+        .collect();
+
+    print_rows(
+        format,
+        vec!["NAME", "ID", "ROOM", "STATUS", "LIGHTS"],
+        &scenes,
+        |scene| {
+            vec![
+                scene.name.clone(),
+                scene.id.clone(),
+                optional(&scene.room),
+                scene.status.clone(),
+                scene.lights.to_string(),
+            ]
+        },
+    )
+}
+
+pub async fn recall(
+    config_path: &str,
+    bridge: Option<&str>,
+    name: &str,
+    room: Option<&str>,
+    dynamic: bool,
+    brightness: Option<f32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let hue_client = connect(config_path, bridge).await?;
+
+    let room_id = match room {
+        Some(name) => {
+            let mut rooms = hue_client.rooms().await?;
+            rooms.extend(hue_client.zones().await?);
+            Some(find_room_id(&rooms, name)?)
+        }
+        None => None,
+    };
+
+    let scenes = hue_client.scenes().await?;
+    let matches: Vec<_> = scenes
+        .iter()
+        .filter(|scene| scene.metadata.name == name)
+        .filter(|scene| room_id.is_none() || room_id.as_ref() == Some(&scene.group.id))
+        .collect();
+
+    let scene = match matches.as_slice() {
+        [scene] => scene,
+        [] => {
+            return Err(format!(
+                "scene {:?} not found; run `nanohue hue scenes` to list them",
+                name
+            )
+            .into())
+        }
+        _ => {
+            return Err(format!(
+                "{} scenes are named {:?}; pass --room to pick one",
+                matches.len(),
+                name
+            )
+            .into())
+        }
+    };
+
+    let recall = SceneRecall {
+        action: if dynamic {
+            RecallAction::DynamicPalette
+        } else {
+            RecallAction::Active
+        },
+        duration: None,
+        dimming: brightness.map(|brightness| Dimming { brightness }),
+    };
+    hue_client.recall_scene(&scene.id, recall).await?;
+
+    println!("Recalled scene {:?}.", scene.metadata.name);
+    Ok(())
+}
+
+pub async fn entertainment(
+    config_path: &str,
+    bridge: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let hue_client = connect(config_path, bridge).await?;
+
+    let (areas, services, lights) = tokio::try_join!(
+        hue_client.entertainment_configurations(),
+        hue_client.entertainment_services(),
+        hue_client.lights()
+    )?;
+
+    let light_names: HashMap<String, String> = lights
+        .into_iter()
+        .map(|light| (light.id, light.metadata.name))
+        .collect();
+
+    let areas: Vec<EntertainmentSummary> = areas
+        .into_iter()
+        .map(|area| EntertainmentSummary {
+            lights: area
+                .light_positions(&services)
+                .into_iter()
+                .map(|(id, position)| EntertainmentLight {
+                    name: light_names.get(&id).cloned(),
+                    id,
+                    position,
+                })
+                .collect(),
+            id: area.id,
+            name: area.metadata.name,
+            configuration_type: area.configuration_type,
+            status: area.status,
+        })
+        .collect();
+
+    print_rows(
+        format,
+        vec!["NAME", "ID", "TYPE", "STATUS", "LIGHTS"],
+        &areas,
+        |area| {
+            let lights: Vec<String> = area
+                .lights
+                .iter()
+                .map(|light| {
+                    format!(
+                        "{} ({:.2},{:.2},{:.2})",
+                        light.name.as_deref().unwrap_or(&light.id),
+                        light.position.x,
+                        light.position.y,
+                        light.position.z
+                    )
+                })
+                .collect();
+
+            vec![
+                area.name.clone(),
+                area.id.clone(),
+                area.configuration_type.clone(),
+                area.status.clone(),
+                lights.join(", "),
+            ]
+        },
+    )
+}
@@ -0,0 +1,308 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use futures_util::StreamExt;
+use serde::Serialize;
+
+use crate::{
+    bridge::connect_nanoleaf,
+    config::{read_section, NanoleafConfig},
+    nanoleaf::{
+        client::Nanoleaf,
+        error::NanoleafError,
+        stream::PanelFrame,
+        types::{
+            EventType, Gesture, LayoutChange, NanoleafEvent, Panel, PanelPosition, StateChange,
+        },
+    },
+};
+
+use super::output::{print_fields, print_rows, OutputFormat};
+
+#[derive(Debug, Serialize)]
+struct EffectSummary {
+    name: String,
+    selected: bool,
+}
+
+/// Connect to the device in the `nanoleaf` section, or the named one in `nanoleaf_devices`.
+async fn connect(
+    config_path: &str,
+    device: Option<&str>,
+) -> Result<Nanoleaf, Box<dyn std::error::Error>> {
+    let config: NanoleafConfig = match device {
+        Some(name) => {
+            let mut devices: BTreeMap<String, NanoleafConfig> =
+                read_section(config_path, "nanoleaf_devices")?;
+            devices
+                .remove(name)
+                .ok_or_else(|| format!("no device named {:?} in nanoleaf_devices", name))?
+        }
+        None => read_section(config_path, "nanoleaf")?,
+    };
+    connect_nanoleaf(&config).await
+}
+
+async fn get_panel(
+    config_path: &str,
+    device: Option<&str>,
+) -> Result<Panel, Box<dyn std::error::Error>> {
+    let nanoleaf = connect(config_path, device).await?;
+
+    Ok(nanoleaf.get_panel().await?)
+}
+
+pub async fn info(
+    config_path: &str,
+    device: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let panel = get_panel(config_path, device).await?;
+
+    print_fields(
+        format,
+        &panel,
+        vec![
+            ("Name", panel.name.clone()),
+            ("Model", panel.model.clone()),
+            ("Manufacturer", panel.manufacturer.clone()),
+            ("Serial number", panel.serial_number.clone()),
+            ("Firmware version", panel.firmware_version.clone()),
+            (
+                "Hardware version",
+                panel
+                    .hardware_version
+                    .clone()
+                    .unwrap_or_else(|| String::from("-")),
+            ),
+            ("Panels", panel.panel_layout.layout.num_panels.to_string()),
+            ("Selected effect", panel.effects.select.clone()),
+        ],
+    )
+}
+
+pub async fn layout(
+    config_path: &str,
+    device: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let panel = get_panel(config_path, device).await?;
+    let layout = panel.panel_layout;
+
+    if let OutputFormat::Json = format {
+        println!("{}", serde_json::to_string_pretty(&layout)?);
+        return Ok(());
+    }
+
+    println!(
+        "Global orientation: {} ({}-{}), side length: {}",
+        layout.global_orientation.value,
+        layout.global_orientation.min,
+        layout.global_orientation.max,
+        layout.layout.side_length
+    );
+
+    print_rows(
+        format,
+        vec!["PANEL", "X", "Y", "ORIENTATION", "SHAPE"],
+        &layout.layout.position_data,
+        |position| {
+            vec![
+                position.panel_id.to_string(),
+                position.x.to_string(),
+                position.y.to_string(),
+                position.orientation.to_string(),
+                position.shape_name().to_string(),
+            ]
+        },
+    )
+}
+
+pub async fn effects(
+    config_path: &str,
+    device: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let panel = get_panel(config_path, device).await?;
+
+    let effects: Vec<EffectSummary> = panel
+        .effects
+        .effects_list
+        .iter()
+        .map(|name| EffectSummary {
+            selected: *name == panel.effects.select,
+            name: name.clone(),
+        })
+        .collect();
+
+    print_rows(format, vec!["EFFECT", "SELECTED"], &effects, |effect| {
+        vec![
+            effect.name.clone(),
+            if effect.selected { "*" } else { "" }.to_string(),
+        ]
+    })
+}
+
+pub async fn state(
+    config_path: &str,
+    device: Option<&str>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let panel = get_panel(config_path, device).await?;
+    let state = panel.state;
+
+    print_fields(
+        format,
+        &state,
+        vec![
+            ("On", state.on.value.to_string()),
+            ("Brightness", state.brightness.value.to_string()),
+            ("Color mode", state.color_mode.clone()),
+            ("Hue", state.hue.value.to_string()),
+            ("Saturation", state.sat.value.to_string()),
+            ("Color temperature", state.ct.value.to_string()),
+            ("Selected effect", panel.effects.select.clone()),
+        ],
+    )
+}
+
+/// Light up each panel in turn using external control, so that panel IDs can be
+/// matched up with their place on the wall. The previous effect or color is
+/// restored afterwards.
+pub async fn identify(
+    config_path: &str,
+    device: Option<&str>,
+    duration: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let nanoleaf = connect(config_path, device).await?;
+    let panel = nanoleaf.get_panel().await?;
+
+    let panels: Vec<&PanelPosition> = panel
+        .panel_layout
+        .layout
+        .position_data
+        .iter()
+        .filter(|position| position.is_lit())
+        .collect();
+
+    let frame = |lit: Option<u16>| -> Vec<PanelFrame> {
+        panels
+            .iter()
+            .map(|position| {
+                let level = if Some(position.panel_id) == lit {
+                    255
+                } else {
+                    0
+                };
+                PanelFrame {
+                    panel_id: position.panel_id,
+                    red: level,
+                    green: level,
+                    blue: level,
+                    white: 0,
+                    transition_time: 1,
+                }
+            })
+            .collect()
+    };
+
+    let mut stream = nanoleaf.start_external_control().await?;
+
+    let result = async {
+        for position in &panels {
+            println!(
+                "Panel {} at ({}, {}), {}",
+                position.panel_id,
+                position.x,
+                position.y,
+                position.shape_name()
+            );
+            stream.send(&frame(Some(position.panel_id))).await?;
+            tokio::time::sleep(duration).await;
+        }
+
+        stream.send(&frame(None)).await
+    }
+    .await;
+
+    restore(&nanoleaf, &panel).await?;
+
+    Ok(result?)
+}
+
+/// Put the controller back the way it was. Effects with names like `*Solid*`
+/// aren't saved on the controller, so they can't be selected again. A solid
+/// color or white is set from the previous state instead.
+async fn restore(nanoleaf: &Nanoleaf, panel: &Panel) -> Result<(), NanoleafError> {
+    let state = &panel.state;
+    let effect = &panel.effects.select;
+
+    if !effect.starts_with('*') {
+        nanoleaf.select_effect(effect).await?;
+    } else if state.color_mode == "ct" {
+        nanoleaf.set_color_temperature(state.ct.value).await?;
+    } else if state.color_mode == "hs" {
+        nanoleaf.set_color(state.hue.value, state.sat.value).await?;
+    } else {
+        eprintln!(
+            "The previous effect {:?} isn't saved on the Nanoleaf, so it can't be restored.",
+            effect
+        );
+    }
+
+    nanoleaf.set_brightness(state.brightness.value, 0).await?;
+    if !state.on.value {
+        nanoleaf.set_power(false).await?;
+    }
+
+    Ok(())
+}
+
+/// A line describing an event, for printing.
+fn describe_event(event: &NanoleafEvent) -> String {
+    match event {
+        NanoleafEvent::State(change) => match change {
+            StateChange::On(on) => format!("Power: {}", if *on { "on" } else { "off" }),
+            StateChange::Brightness(brightness) => format!("Brightness: {}", brightness),
+            StateChange::Hue(hue) => format!("Hue: {}", hue),
+            StateChange::Saturation(saturation) => format!("Saturation: {}", saturation),
+            StateChange::ColorTemperature(kelvin) => format!("Color temperature: {}K", kelvin),
+            StateChange::ColorMode(mode) => format!("Color mode: {}", mode),
+        },
+        NanoleafEvent::Layout(LayoutChange::Layout(layout)) => {
+            format!("Layout: {} panels", layout.num_panels)
+        }
+        NanoleafEvent::Layout(LayoutChange::GlobalOrientation(orientation)) => {
+            format!("Orientation: {}°", orientation)
+        }
+        NanoleafEvent::Effect(name) => format!("Effect: {}", name),
+        NanoleafEvent::Touch { panel_id, gesture } => {
+            let gesture = match gesture {
+                Gesture::SingleTap => String::from("single tap"),
+                Gesture::DoubleTap => String::from("double tap"),
+                Gesture::SwipeUp => String::from("swipe up"),
+                Gesture::SwipeDown => String::from("swipe down"),
+                Gesture::SwipeLeft => String::from("swipe left"),
+                Gesture::SwipeRight => String::from("swipe right"),
+                Gesture::Other(gesture) => format!("gesture {}", gesture),
+            };
+            match panel_id {
+                Some(panel_id) => format!("Touch: {} on panel {}", gesture, panel_id),
+                None => format!("Touch: {}", gesture),
+            }
+        }
+    }
+}
+
+pub async fn events(
+    config_path: &str,
+    device: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let nanoleaf = connect(config_path, device).await?;
+    let mut events = nanoleaf.events(&EventType::ALL).await?;
+
+    while let Some(event) = events.next().await {
+        println!("{}", describe_event(&event));
+    }
+
+    Err("the controller closed the event stream".into())
+}
@@ -0,0 +1,104 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// A plain-text table with columns padded to the widest value.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: Vec<&'static str>) -> Table {
+        Table {
+            headers,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn print(&self) {
+        let mut widths: Vec<usize> = self.headers.iter().map(|header| header.len()).collect();
+        for row in &self.rows {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+
+        let headers: Vec<String> = self.headers.iter().map(|h| h.to_string()).collect();
+        print_row(&headers, &widths);
+        for row in &self.rows {
+            print_row(row, &widths);
+        }
+    }
+}
+
+fn print_row(row: &[String], widths: &[usize]) {
+    let line: Vec<String> = row
+        .iter()
+        .zip(widths)
+        .map(|(value, width)| format!("{:<width$}", value, width = width))
+        .collect();
+
+    println!("{}", line.join("  ").trim_end());
+}
+
+/// Print rows either as a table, or as a JSON array of the serialized rows.
+pub fn print_rows<T, F>(
+    format: OutputFormat,
+    headers: Vec<&'static str>,
+    rows: &[T],
+    to_row: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    T: Serialize,
+    F: Fn(&T) -> Vec<String>,
+{
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(rows)?),
+        OutputFormat::Table => {
+            let mut table = Table::new(headers);
+            for row in rows {
+                table.add_row(to_row(row));
+            }
+            table.print();
+        }
+    }
+
+    Ok(())
+}
+
+/// Print a single value either as aligned `name: value` lines, or as JSON.
+pub fn print_fields<T>(
+    format: OutputFormat,
+    value: &T,
+    fields: Vec<(&'static str, String)>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    T: Serialize,
+{
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        OutputFormat::Table => {
+            let width = fields.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+            for (name, value) in fields {
+                println!(
+                    "{:<width$}  {}",
+                    format!("{}:", name),
+                    value,
+                    width = width + 1
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::update_config,
+    hue::{client::Hue, error::HueError},
+    nanoleaf::{client::Nanoleaf, error::NanoleafError},
+};
+
+const HUE_DEVICE_TYPE: &str = "nanohue#nanohue";
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const NANOLEAF_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pair with a Hue bridge, storing the credentials in the `hue` section, or
+/// under `bridges` when a name is given.
+pub async fn pair_hue(
+    host: &str,
+    name: Option<&str>,
+    config_path: &str,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Press the link button on the Hue bridge at {}. Waiting up to {} seconds...",
+        host,
+        timeout.as_secs()
+    );
+
+    let deadline = Instant::now() + timeout;
+
+    let credentials = loop {
+        match Hue::pair(host, HUE_DEVICE_TYPE).await {
+            Ok(credentials) => break credentials,
+            Err(HueError::LinkButtonNotPressed) => {
+                trace!(target: "nanohue", "Link button has not been pressed yet.");
+            }
+            Err(err) if err.is_retryable() => {
+                warn!(target: "nanohue", "Unable to reach the Hue bridge. {}", err);
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        if Instant::now() + POLL_INTERVAL > deadline {
+            return Err(format!(
+                "the link button was not pressed within {} seconds",
+                timeout.as_secs()
+            )
+            .into());
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    };
+
+    let section = match name {
+        Some(name) => vec!["bridges", name],
+        None => vec!["hue"],
+    };
+
+    // nanohue doesn't use the client key, but the bridge only hands it out
+    // when pairing, so it's kept for other tools.
+    update_config(
+        config_path,
+        &section,
+        vec![
+            ("host", host.to_string()),
+            ("username", credentials.username),
+            ("client_key", credentials.client_key),
+        ],
+    )?;
+
+    println!(
+        "Paired with the Hue bridge. Credentials written to {} under {}.",
+        config_path,
+        section.join(".")
+    );
+
+    Ok(())
+}
+
+/// Pair with a Nanoleaf, storing the token in the `nanoleaf` section, or under
+/// `nanoleaf_devices` when a name is given.
+pub async fn pair_nanoleaf(
+    host: &str,
+    name: Option<&str>,
+    config_path: &str,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Hold the power button on the Nanoleaf at {} for 5-7 seconds, until the lights start flashing.",
+        host
+    );
+
+    let deadline = Instant::now() + timeout;
+    let mut last_report = Instant::now();
+
+    let token = loop {
+        match Nanoleaf::pair(host).await {
+            Ok(token) => break token,
+            Err(NanoleafError::NotInPairingMode) => {
+                trace!(target: "nanohue", "The Nanoleaf is not in pairing mode yet.");
+            }
+            Err(err) if err.is_retryable() => {
+                warn!(target: "nanohue", "Unable to reach the Nanoleaf. {}", err);
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let now = Instant::now();
+        if now + NANOLEAF_POLL_INTERVAL > deadline {
+            return Err(format!(
+                "the Nanoleaf did not enter pairing mode within {} seconds",
+                timeout.as_secs()
+            )
+            .into());
+        }
+
+        if now.duration_since(last_report) >= Duration::from_secs(5) {
+            println!(
+                "Still waiting for pairing mode, {} seconds remaining...",
+                deadline.duration_since(now).as_secs()
+            );
+            last_report = now;
+        }
+
+        tokio::time::sleep(NANOLEAF_POLL_INTERVAL).await;
+    };
+
+    let section = match name {
+        Some(name) => vec!["nanoleaf_devices", name],
+        None => vec!["nanoleaf"],
+    };
+
+    update_config(
+        config_path,
+        &section,
+        vec![("host", host.to_string()), ("token", token)],
+    )?;
+
+    println!(
+        "Paired with the Nanoleaf. Token written to {} under {}.",
+        config_path,
+        section.join(".")
+    );
+
+    Ok(())
+}
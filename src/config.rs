@@ -0,0 +1,1006 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_yaml::{Mapping, Value};
+use thiserror::Error;
+
+use crate::spatial::{LayoutMode, Position};
+
+/// Every problem found with the configuration, so they can all be fixed at once.
+#[derive(Debug, Error)]
+#[error("configuration check failed:\n{}", .0.iter().map(|problem| format!("  - {}", problem)).collect::<Vec<_>>().join("\n"))]
+pub struct ConfigError(pub Vec<String>);
+
+#[derive(Debug, Deserialize)]
+pub struct HueConfig {
+    pub host: Option<String>,
+    // Used to look up the bridge's address on the network, in case it changes.
+    pub bridge_id: Option<String>,
+    // Shorthand for a single mapping from this room to the `nanoleaf` device.
+    pub group: Option<String>,
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NanoleafConfig {
+    pub host: Option<String>,
+    // Used to look up the controller's address on the network, in case it changes.
+    pub device_id: Option<String>,
+    pub token: String,
+    #[serde(default = "default_max_brightness")]
+    pub max_brightness: u8,
+}
+
+fn default_max_brightness() -> u8 {
+    100
+}
+
+/// An inclusive range of times, in tenths of a second.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct TimeRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+/// How the effect written to the Nanoleaf animates between the palette's colors.
+/// Dynamic scenes use the faster `dynamic_*` timings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EffectConfig {
+    pub min_brightness: u32,
+    pub transition_time: TimeRange,
+    pub delay_time: TimeRange,
+    pub dynamic_transition_time: TimeRange,
+    pub dynamic_delay_time: TimeRange,
+    pub palette: PaletteConfig,
+}
+
+/// How the lights' colors are combined into the effect's palette. Colors that
+/// look alike are merged, so a room full of bulbs doesn't produce a palette
+/// of near duplicates.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PaletteConfig {
+    /// Colors closer together than this are merged, measured in ΔE2000. A
+    /// difference of around 2 is barely noticeable.
+    pub merge_threshold: f32,
+    /// The most colors the palette may have. The closest colors are merged
+    /// until it fits.
+    pub max_colors: usize,
+}
+
+impl Default for PaletteConfig {
+    fn default() -> Self {
+        PaletteConfig {
+            merge_threshold: 5.0,
+            max_colors: 8,
+        }
+    }
+}
+
+impl Default for EffectConfig {
+    fn default() -> Self {
+        EffectConfig {
+            min_brightness: 25,
+            transition_time: TimeRange { min: 30, max: 60 },
+            delay_time: TimeRange { min: 60, max: 90 },
+            dynamic_transition_time: TimeRange { min: 15, max: 30 },
+            dynamic_delay_time: TimeRange { min: 30, max: 60 },
+            palette: PaletteConfig::default(),
+        }
+    }
+}
+
+/// Draws each light's color on the panels nearest to it, by streaming colors
+/// to the controller rather than writing an effect.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LayoutConfig {
+    #[serde(default)]
+    pub mode: LayoutMode,
+    /// The name of an entertainment area to take the lights' positions from.
+    /// While an app or sync box is streaming to it, its lights are sampled
+    /// once a second, since the stream itself can't be read.
+    pub entertainment: Option<String>,
+    /// Light names, and where they sit in the room. These take precedence over
+    /// the entertainment area's positions.
+    #[serde(default)]
+    pub lights: BTreeMap<String, Position>,
+    /// Panel IDs, and the name of the light each one should always show.
+    #[serde(default)]
+    pub panels: BTreeMap<u16, String>,
+    /// How long each panel fades to a new color, in tenths of a second.
+    #[serde(default = "default_transition_time")]
+    pub transition_time: u16,
+}
+
+fn default_transition_time() -> u16 {
+    10
+}
+
+/// The kind of Hue resource a mapping takes its colors and brightness from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    Room,
+    Zone,
+    Light,
+}
+
+impl fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceKind::Room => write!(f, "room"),
+            SourceKind::Zone => write!(f, "zone"),
+            SourceKind::Light => write!(f, "light"),
+        }
+    }
+}
+
+/// Binds a Hue room, zone or light to the Nanoleaf devices that should mirror
+/// it. Exactly one of `room`, `zone` and `light` must be set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MappingConfig {
+    /// Name of an entry in `bridges`. Defaults to the `hue` section.
+    pub bridge: Option<String>,
+    pub room: Option<String>,
+    pub zone: Option<String>,
+    pub light: Option<String>,
+    /// Names of entries in `nanoleaf_devices`, or `default` for the `nanoleaf` section.
+    pub nanoleaf: Vec<String>,
+    /// Defaults to the lowest `max_brightness` of the mapping's devices.
+    pub max_brightness: Option<u8>,
+    #[serde(default)]
+    pub effect: EffectConfig,
+    pub layout: Option<LayoutConfig>,
+    /// Send changes made on the Nanoleaf, such as turning it off, back to Hue.
+    #[serde(default)]
+    pub reverse_sync: bool,
+}
+
+/// The name given to the bridge in the `hue` section, and the device in the
+/// `nanoleaf` section.
+pub const DEFAULT_NAME: &str = "default";
+
+#[derive(Debug, Deserialize)]
+pub struct NanoHueConfig {
+    pub hue: Option<HueConfig>,
+    #[serde(default)]
+    pub bridges: BTreeMap<String, HueConfig>,
+    pub nanoleaf: Option<NanoleafConfig>,
+    #[serde(default)]
+    pub nanoleaf_devices: BTreeMap<String, NanoleafConfig>,
+    #[serde(default)]
+    pub mappings: Vec<MappingConfig>,
+}
+
+impl HueConfig {
+    fn validate(&self, key: &str, problems: &mut Vec<String>) {
+        if self.host.is_none() && self.bridge_id.is_none() {
+            problems.push(format!("{0}.host or {0}.bridge_id must be set", key));
+        }
+
+        if self.username.trim().is_empty() {
+            problems.push(format!(
+                "{}.username is empty; run `nanohue pair-hue` to create one",
+                key
+            ));
+        }
+
+        match &self.group {
+            Some(_) if key != bridge_key(DEFAULT_NAME) => problems.push(format!(
+                "{}.group is only supported in the hue section; add an entry to mappings instead",
+                key
+            )),
+            Some(group) if group.trim().is_empty() => problems.push(format!(
+                "{}.group is empty; run `nanohue hue rooms` to list the rooms",
+                key
+            )),
+            _ => {}
+        }
+    }
+}
+
+impl NanoleafConfig {
+    fn validate(&self, key: &str, problems: &mut Vec<String>) {
+        if self.host.is_none() && self.device_id.is_none() {
+            problems.push(format!("{0}.host or {0}.device_id must be set", key));
+        }
+
+        if self.token.trim().is_empty() {
+            problems.push(format!(
+                "{}.token is empty; run `nanohue pair-nanoleaf` to create one",
+                key
+            ));
+        }
+
+        if self.max_brightness > 100 {
+            problems.push(format!(
+                "{}.max_brightness must be between 0 and 100, got {}",
+                key, self.max_brightness
+            ));
+        }
+    }
+}
+
+impl TimeRange {
+    fn validate(&self, key: &str, problems: &mut Vec<String>) {
+        if self.min > self.max {
+            problems.push(format!(
+                "{}.min must not be greater than {}.max, got {} and {}",
+                key, key, self.min, self.max
+            ));
+        }
+    }
+}
+
+impl EffectConfig {
+    fn validate(&self, key: &str, problems: &mut Vec<String>) {
+        if self.min_brightness > 100 {
+            problems.push(format!(
+                "{}.min_brightness must be between 0 and 100, got {}",
+                key, self.min_brightness
+            ));
+        }
+
+        let ranges = [
+            ("transition_time", &self.transition_time),
+            ("delay_time", &self.delay_time),
+            ("dynamic_transition_time", &self.dynamic_transition_time),
+            ("dynamic_delay_time", &self.dynamic_delay_time),
+        ];
+
+        for (name, range) in ranges {
+            range.validate(&format!("{}.{}", key, name), problems);
+        }
+
+        self.palette.validate(&format!("{}.palette", key), problems);
+    }
+}
+
+impl PaletteConfig {
+    fn validate(&self, key: &str, problems: &mut Vec<String>) {
+        if self.merge_threshold.is_nan() || self.merge_threshold < 0.0 {
+            problems.push(format!(
+                "{}.merge_threshold must not be negative, got {}",
+                key, self.merge_threshold
+            ));
+        }
+
+        if self.max_colors == 0 {
+            problems.push(format!("{}.max_colors must be at least 1", key));
+        }
+    }
+}
+
+impl LayoutConfig {
+    fn validate(&self, key: &str, problems: &mut Vec<String>) {
+        for (name, position) in &self.lights {
+            if !(-1.0..=1.0).contains(&position.x) || !(-1.0..=1.0).contains(&position.y) {
+                problems.push(format!(
+                    "{}.lights.{} must be within -1..1 on both axes, got ({}, {})",
+                    key, name, position.x, position.y
+                ));
+            }
+        }
+
+        match self.mode {
+            LayoutMode::Nearest | LayoutMode::Gradient
+                if self.lights.is_empty() && self.entertainment.is_none() =>
+            {
+                problems.push(format!(
+                    "{}.lights must give the position of at least one light, unless {}.entertainment is set",
+                    key, key
+                ))
+            }
+            LayoutMode::Manual if self.panels.is_empty() => problems.push(format!(
+                "{}.panels must assign at least one panel; run `nanohue nanoleaf identify` to find their IDs",
+                key
+            )),
+            _ => {}
+        }
+    }
+}
+
+impl MappingConfig {
+    /// The kind and name of the resource to mirror, if exactly one is set.
+    pub fn source(&self) -> Option<(SourceKind, &str)> {
+        let sources = [
+            (SourceKind::Room, &self.room),
+            (SourceKind::Zone, &self.zone),
+            (SourceKind::Light, &self.light),
+        ];
+
+        let mut set = sources
+            .into_iter()
+            .filter_map(|(kind, name)| name.as_deref().map(|name| (kind, name)));
+
+        match (set.next(), set.next()) {
+            (Some(source), None) => Some(source),
+            _ => None,
+        }
+    }
+
+    /// The name of the bridge the mapping's source lives on.
+    pub fn bridge(&self) -> &str {
+        self.bridge.as_deref().unwrap_or(DEFAULT_NAME)
+    }
+
+    fn validate(
+        &self,
+        key: &str,
+        bridges: &BTreeMap<&str, &HueConfig>,
+        devices: &BTreeMap<&str, &NanoleafConfig>,
+        problems: &mut Vec<String>,
+    ) {
+        if !bridges.contains_key(self.bridge()) {
+            let known: Vec<&str> = bridges.keys().copied().collect();
+            problems.push(format!(
+                "{}.bridge refers to an unknown bridge '{}'; known bridges: {}",
+                key,
+                self.bridge(),
+                known.join(", ")
+            ));
+        }
+
+        match self.source() {
+            Some((kind, name)) if name.trim().is_empty() => problems.push(format!(
+                "{}.{} is empty; run `nanohue hue {}s` to list them",
+                key, kind, kind
+            )),
+            Some(_) => {}
+            None => problems.push(format!(
+                "{} must set exactly one of room, zone or light",
+                key
+            )),
+        }
+
+        if self.nanoleaf.is_empty() {
+            problems.push(format!("{}.nanoleaf must list at least one device", key));
+        }
+
+        for name in &self.nanoleaf {
+            if !devices.contains_key(name.as_str()) {
+                let known: Vec<&str> = devices.keys().copied().collect();
+                problems.push(format!(
+                    "{}.nanoleaf refers to an unknown device '{}'; known devices: {}",
+                    key,
+                    name,
+                    known.join(", ")
+                ));
+            }
+        }
+
+        if let Some(max_brightness) = self.max_brightness {
+            if max_brightness > 100 {
+                problems.push(format!(
+                    "{}.max_brightness must be between 0 and 100, got {}",
+                    key, max_brightness
+                ));
+            }
+        }
+
+        self.effect.validate(&format!("{}.effect", key), problems);
+
+        if let Some(layout) = &self.layout {
+            if self.light.is_some() {
+                problems.push(format!(
+                    "{}.layout needs a room or zone to take light positions from",
+                    key
+                ));
+            }
+
+            layout.validate(&format!("{}.layout", key), problems);
+        }
+    }
+}
+
+/// The key a bridge's settings live under, for use in error messages.
+pub fn bridge_key(name: &str) -> String {
+    if name == DEFAULT_NAME {
+        String::from("hue")
+    } else {
+        format!("bridges.{}", name)
+    }
+}
+
+/// The key a device's settings live under, for use in error messages.
+pub fn device_key(name: &str) -> String {
+    if name == DEFAULT_NAME {
+        String::from("nanoleaf")
+    } else {
+        format!("nanoleaf_devices.{}", name)
+    }
+}
+
+impl NanoHueConfig {
+    /// Every configured bridge by name, including the `hue` section as `default`.
+    pub fn bridges(&self) -> BTreeMap<&str, &HueConfig> {
+        let mut bridges: BTreeMap<&str, &HueConfig> = self
+            .bridges
+            .iter()
+            .map(|(name, bridge)| (name.as_str(), bridge))
+            .collect();
+
+        if let Some(hue) = &self.hue {
+            bridges.insert(DEFAULT_NAME, hue);
+        }
+
+        bridges
+    }
+
+    /// Every configured Nanoleaf by name, including the `nanoleaf` section as `default`.
+    pub fn devices(&self) -> BTreeMap<&str, &NanoleafConfig> {
+        let mut devices: BTreeMap<&str, &NanoleafConfig> = self
+            .nanoleaf_devices
+            .iter()
+            .map(|(name, device)| (name.as_str(), device))
+            .collect();
+
+        if let Some(nanoleaf) = &self.nanoleaf {
+            devices.insert(DEFAULT_NAME, nanoleaf);
+        }
+
+        devices
+    }
+
+    /// Every room to mirror, including the one given by `hue.group`.
+    pub fn mappings(&self) -> Vec<MappingConfig> {
+        let group = self.hue.as_ref().and_then(|hue| hue.group.as_ref());
+        let legacy = group.map(|group| MappingConfig {
+            bridge: None,
+            room: Some(group.clone()),
+            zone: None,
+            light: None,
+            nanoleaf: vec![String::from(DEFAULT_NAME)],
+            max_brightness: None,
+            effect: EffectConfig::default(),
+            layout: None,
+            reverse_sync: false,
+        });
+
+        legacy.into_iter().chain(self.mappings.clone()).collect()
+    }
+
+    /// The brightness cap for a mapping, falling back to the strictest of its devices.
+    pub fn max_brightness(&self, mapping: &MappingConfig) -> u8 {
+        let devices = self.devices();
+
+        mapping.max_brightness.unwrap_or_else(|| {
+            mapping
+                .nanoleaf
+                .iter()
+                .filter_map(|name| devices.get(name.as_str()))
+                .map(|device| device.max_brightness)
+                .min()
+                .unwrap_or_else(default_max_brightness)
+        })
+    }
+
+    /// Check for missing or out-of-range values, without contacting any devices.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if let Some(hue) = &self.hue {
+            hue.validate(&bridge_key(DEFAULT_NAME), &mut problems);
+        }
+
+        for (name, bridge) in &self.bridges {
+            bridge.validate(&bridge_key(name), &mut problems);
+        }
+
+        if self.hue.is_none() && self.bridges.is_empty() {
+            problems.push(String::from(
+                "no Hue bridge configured; add a hue section or an entry to bridges",
+            ));
+        }
+
+        if self.bridges.contains_key(DEFAULT_NAME) {
+            problems.push(format!(
+                "bridges.{} is reserved for the hue section; choose another name",
+                DEFAULT_NAME
+            ));
+        }
+
+        if let Some(nanoleaf) = &self.nanoleaf {
+            nanoleaf.validate(&device_key(DEFAULT_NAME), &mut problems);
+        }
+
+        if self.nanoleaf_devices.contains_key(DEFAULT_NAME) {
+            problems.push(format!(
+                "nanoleaf_devices.{} is reserved for the nanoleaf section; choose another name",
+                DEFAULT_NAME
+            ));
+        }
+
+        for (name, device) in &self.nanoleaf_devices {
+            device.validate(&device_key(name), &mut problems);
+        }
+
+        let group = self.hue.as_ref().and_then(|hue| hue.group.as_ref());
+
+        if group.is_some() && self.nanoleaf.is_none() {
+            problems.push(String::from(
+                "hue.group is set, but there is no nanoleaf section to mirror it to",
+            ));
+        }
+
+        if group.is_none() && self.mappings.is_empty() {
+            problems.push(String::from(
+                "nothing to mirror; set hue.group or add an entry to mappings",
+            ));
+        }
+
+        let bridges = self.bridges();
+        let devices = self.devices();
+        for (index, mapping) in self.mappings.iter().enumerate() {
+            mapping.validate(
+                &format!("mappings[{}]", index),
+                &bridges,
+                &devices,
+                &mut problems,
+            );
+        }
+
+        // Two mappings writing to the same device would keep replacing each
+        // other's effect.
+        let keys = group
+            .map(|_| String::from("hue.group"))
+            .into_iter()
+            .chain((0..self.mappings.len()).map(|index| format!("mappings[{}]", index)));
+        let mut owners: BTreeMap<&str, String> = BTreeMap::new();
+        for (key, mapping) in keys.zip(&self.mappings()) {
+            for name in &mapping.nanoleaf {
+                match owners.get(name.as_str()) {
+                    Some(owner) if *owner == key => {}
+                    Some(owner) => problems.push(format!(
+                        "{}.nanoleaf uses device '{}', which {} already mirrors to; a device can only belong to one mapping",
+                        key, name, owner
+                    )),
+                    None => {
+                        owners.insert(name, key.clone());
+                    }
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+}
+
+pub fn read_config(path: &str) -> Result<NanoHueConfig, Box<dyn std::error::Error>> {
+    let f = std::fs::File::open(path)?;
+    let config: NanoHueConfig = serde_yaml::from_reader::<std::fs::File, NanoHueConfig>(f)?;
+
+    Ok(config)
+}
+
+/// Read a single top-level section of the configuration file, so that commands
+/// which only talk to one device don't require the rest to be filled in.
+pub fn read_section<T>(path: &str, section: &str) -> Result<T, Box<dyn std::error::Error>>
+where
+    T: DeserializeOwned,
+{
+    let f = std::fs::File::open(path)?;
+    let root: Value = serde_yaml::from_reader::<std::fs::File, Value>(f)?;
+
+    let value = root
+        .get(section)
+        .cloned()
+        .ok_or_else(|| format!("the configuration file has no {:?} section", section))?;
+
+    Ok(serde_yaml::from_value::<T>(value)?)
+}
+
+/// Set the given keys within a section of the configuration file, leaving
+/// every other value untouched. The section is given as a path of keys from
+/// the top level, and is created along with the file if it doesn't exist yet.
+///
+/// The file is parsed and written back out, so any comments in it are lost
+/// and the keys may be reformatted.
+pub fn update_config(
+    path: &str,
+    section: &[&str],
+    values: Vec<(&str, String)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut root = match std::fs::read_to_string(path) {
+        Ok(contents) if !contents.trim().is_empty() => serde_yaml::from_str::<Value>(&contents)?,
+        Ok(_) => Value::Mapping(Mapping::new()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Value::Mapping(Mapping::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut current = root
+        .as_mapping_mut()
+        .ok_or("the configuration file must contain a YAML mapping")?;
+
+    for key in section {
+        let entry = current
+            .entry(Value::from(*key))
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+        if entry.is_null() {
+            *entry = Value::Mapping(Mapping::new());
+        }
+
+        current = entry
+            .as_mapping_mut()
+            .ok_or_else(|| format!("the configuration section {:?} must be a YAML mapping", key))?;
+    }
+
+    for (key, value) in values {
+        current.insert(Value::from(key), Value::from(value));
+    }
+
+    write_config(Path::new(path), &serde_yaml::to_string(&root)?)?;
+
+    Ok(())
+}
+
+/// Replace the configuration file in one step, so that it is never left half
+/// written. It holds application keys and tokens, so only its owner may read
+/// it.
+fn write_config(path: &Path, contents: &str) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let temporary = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+    // A leftover from an earlier attempt may have looser permissions.
+    let _ = fs::remove_file(&temporary);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let written = options.open(&temporary).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+
+    match written.and_then(|_| fs::rename(&temporary, path)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&temporary);
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> NanoHueConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn problems(yaml: &str) -> Vec<String> {
+        match parse(yaml).validate() {
+            Ok(()) => Vec::new(),
+            Err(ConfigError(problems)) => problems,
+        }
+    }
+
+    #[test]
+    fn accepts_a_complete_config() {
+        let config = "
+hue:
+  host: 192.168.1.2
+  username: abc
+  group: Living room
+nanoleaf:
+  host: 192.168.1.3
+  token: def
+";
+        assert_eq!(problems(config), Vec::<String>::new());
+    }
+
+    #[test]
+    fn explains_what_is_wrong() {
+        let cases = [
+            (
+                "hue: {host: 192.168.1.2, username: ' ', group: Living}\nnanoleaf: {host: 192.168.1.3, token: def}",
+                "hue.username is empty; run `nanohue pair-hue` to create one",
+            ),
+            (
+                "hue: {host: 192.168.1.2, username: abc, group: Living}\nnanoleaf: {host: 192.168.1.3, token: ''}",
+                "nanoleaf.token is empty; run `nanohue pair-nanoleaf` to create one",
+            ),
+            (
+                "hue: {username: abc, group: Living}\nnanoleaf: {host: 192.168.1.3, token: def}",
+                "hue.host or hue.bridge_id must be set",
+            ),
+            (
+                "hue: {host: 192.168.1.2, username: abc, group: Living}\nnanoleaf: {token: def}",
+                "nanoleaf.host or nanoleaf.device_id must be set",
+            ),
+            (
+                "hue: {host: 192.168.1.2, username: abc}\nnanoleaf: {host: 192.168.1.3, token: def}\nmappings: []",
+                "nothing to mirror; set hue.group or add an entry to mappings",
+            ),
+            (
+                "hue: {host: 192.168.1.2, username: abc}\nnanoleaf: {host: 192.168.1.3, token: def}\nmappings: [{room: Living, nanoleaf: []}]",
+                "mappings[0].nanoleaf must list at least one device",
+            ),
+            (
+                "nanoleaf: {host: 192.168.1.3, token: def}\nmappings: [{room: Living, nanoleaf: [default]}]",
+                "no Hue bridge configured; add a hue section or an entry to bridges",
+            ),
+            (
+                "hue: {host: 192.168.1.2, username: abc, group: Living}\nnanoleaf: {host: 192.168.1.3, token: def, max_brightness: 101}",
+                "nanoleaf.max_brightness must be between 0 and 100, got 101",
+            ),
+        ];
+
+        for (config, expected) in cases {
+            let problems = problems(config);
+            assert!(
+                problems.iter().any(|problem| problem == expected),
+                "expected {:?} for {:?}, got {:?}",
+                expected,
+                config,
+                problems
+            );
+        }
+    }
+
+    #[test]
+    fn lists_every_problem_at_once() {
+        let err = parse("hue: {username: ''}\nmappings: [{room: Living, nanoleaf: [shelf]}]")
+            .validate()
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "configuration check failed:
+  - hue.host or hue.bridge_id must be set
+  - hue.username is empty; run `nanohue pair-hue` to create one
+  - mappings[0].nanoleaf refers to an unknown device 'shelf'; known devices: "
+        );
+    }
+
+    #[test]
+    fn reads_several_mappings() {
+        let config = parse(
+            "
+hue:
+  host: 192.168.1.2
+  username: abc
+  group: Living room
+nanoleaf:
+  host: 192.168.1.3
+  token: def
+  max_brightness: 80
+nanoleaf_devices:
+  shelf:
+    host: 192.168.1.4
+    token: ghi
+    max_brightness: 60
+  hallway:
+    device_id: 5E:2F:1A:4B:9C:77
+    token: jkl
+mappings:
+  - room: Kitchen
+    nanoleaf: [shelf, hallway]
+    effect:
+      transition_time: {min: 10, max: 20}
+  - room: Office
+    nanoleaf: [hallway]
+    max_brightness: 90
+",
+        );
+        let mappings = config.mappings();
+
+        // The legacy `hue.group` comes first, mirrored to the `nanoleaf` section.
+        assert_eq!(mappings.len(), 3);
+        assert_eq!(
+            mappings[0].source(),
+            Some((SourceKind::Room, "Living room"))
+        );
+        assert_eq!(mappings[0].nanoleaf, vec![DEFAULT_NAME]);
+        assert_eq!(mappings[1].source(), Some((SourceKind::Room, "Kitchen")));
+        assert_eq!(mappings[1].nanoleaf, vec!["shelf", "hallway"]);
+        assert_eq!(mappings[1].effect.transition_time.max, 20);
+        assert_eq!(mappings[2].effect.transition_time.max, 60);
+
+        // Without a limit of its own, a mapping takes the strictest of its devices.
+        assert_eq!(config.max_brightness(&mappings[0]), 80);
+        assert_eq!(config.max_brightness(&mappings[1]), 60);
+        assert_eq!(config.max_brightness(&mappings[2]), 90);
+        assert_eq!(config.devices()["hallway"].max_brightness, 100);
+    }
+
+    #[test]
+    fn reads_zone_and_light_sources() {
+        let config = parse(
+            "
+hue: {host: 192.168.1.2, username: abc}
+nanoleaf_devices:
+  lines: {host: 192.168.1.4, token: ghi}
+  shelf: {host: 192.168.1.5, token: jkl}
+mappings:
+  - zone: TV
+    nanoleaf: [lines]
+  - light: Reading lamp
+    nanoleaf: [shelf]
+",
+        );
+
+        assert!(config.validate().is_ok());
+        let mappings = config.mappings();
+        assert_eq!(mappings[0].source(), Some((SourceKind::Zone, "TV")));
+        assert_eq!(
+            mappings[1].source(),
+            Some((SourceKind::Light, "Reading lamp"))
+        );
+    }
+
+    #[test]
+    fn needs_exactly_one_source() {
+        let devices = "
+hue: {host: 192.168.1.2, username: abc}
+nanoleaf: {host: 192.168.1.3, token: def}
+";
+        let cases = [
+            (
+                "mappings: [{room: Living, zone: TV, nanoleaf: [default]}]",
+                "mappings[0] must set exactly one of room, zone or light",
+            ),
+            (
+                "mappings: [{nanoleaf: [default]}]",
+                "mappings[0] must set exactly one of room, zone or light",
+            ),
+            (
+                "mappings: [{zone: '', nanoleaf: [default]}]",
+                "mappings[0].zone is empty; run `nanohue hue zones` to list them",
+            ),
+            (
+                "mappings: [{light: Desk, nanoleaf: [default], layout: {lights: {Desk: {x: 0, y: 0}}}}]",
+                "mappings[0].layout needs a room or zone to take light positions from",
+            ),
+        ];
+
+        for (mappings, expected) in cases {
+            let config = format!("{}{}", devices, mappings);
+            assert_eq!(problems(&config), vec![expected], "for {:?}", mappings);
+        }
+    }
+
+    #[test]
+    fn reads_several_bridges() {
+        let config = parse(
+            "
+hue: {host: 192.168.1.2, username: abc}
+bridges:
+  upstairs: {bridge_id: 001788fffe4a1f2e, username: def}
+nanoleaf_devices:
+  landing: {host: 192.168.1.4, token: ghi}
+  lounge: {host: 192.168.1.5, token: jkl}
+mappings:
+  - room: Landing
+    bridge: upstairs
+    nanoleaf: [landing]
+  - room: Lounge
+    nanoleaf: [lounge]
+",
+        );
+
+        assert!(config.validate().is_ok());
+        assert!(config
+            .bridges()
+            .keys()
+            .copied()
+            .eq([DEFAULT_NAME, "upstairs"]));
+        assert_eq!(
+            config.bridges()["upstairs"].bridge_id.as_deref(),
+            Some("001788fffe4a1f2e")
+        );
+
+        let mappings = config.mappings();
+        assert_eq!(mappings[0].bridge(), "upstairs");
+        assert_eq!(mappings[1].bridge(), DEFAULT_NAME);
+    }
+
+    #[test]
+    fn checks_the_bridges() {
+        let cases = [
+            (
+                "bridges: {upstairs: {host: 192.168.1.2, username: abc}}\nmappings: [{room: Landing, bridge: downstairs, nanoleaf: [default]}]",
+                "mappings[0].bridge refers to an unknown bridge 'downstairs'; known bridges: upstairs",
+            ),
+            (
+                "bridges: {upstairs: {host: 192.168.1.2, username: abc}}\nmappings: [{room: Landing, nanoleaf: [default]}]",
+                "mappings[0].bridge refers to an unknown bridge 'default'; known bridges: upstairs",
+            ),
+            (
+                "hue: {host: 192.168.1.2, username: abc}\nbridges: {default: {host: 192.168.1.3, username: def}}\nmappings: [{room: Landing, nanoleaf: [default]}]",
+                "bridges.default is reserved for the hue section; choose another name",
+            ),
+            (
+                "bridges: {upstairs: {host: 192.168.1.2, username: abc, group: Landing}}\nmappings: [{room: Landing, bridge: upstairs, nanoleaf: [default]}]",
+                "bridges.upstairs.group is only supported in the hue section; add an entry to mappings instead",
+            ),
+            (
+                "bridges: {upstairs: {username: abc}}\nmappings: [{room: Landing, bridge: upstairs, nanoleaf: [default]}]",
+                "bridges.upstairs.host or bridges.upstairs.bridge_id must be set",
+            ),
+        ];
+
+        for (bridges, expected) in cases {
+            let config = format!("nanoleaf: {{host: 192.168.1.4, token: ghi}}\n{}", bridges);
+            assert_eq!(problems(&config), vec![expected], "for {:?}", bridges);
+        }
+    }
+
+    #[test]
+    fn rejects_two_mappings_with_the_same_device() {
+        let config = "
+hue: {host: 192.168.1.2, username: abc, group: Living room}
+nanoleaf: {host: 192.168.1.3, token: def}
+nanoleaf_devices:
+  shelf: {host: 192.168.1.4, token: ghi}
+mappings:
+  - room: Kitchen
+    nanoleaf: [shelf]
+  - room: Office
+    nanoleaf: [shelf, default]
+";
+
+        assert_eq!(
+            problems(config),
+            vec![
+                "mappings[1].nanoleaf uses device 'shelf', which mappings[0] already mirrors to; a device can only belong to one mapping",
+                "mappings[1].nanoleaf uses device 'default', which hue.group already mirrors to; a device can only belong to one mapping",
+            ]
+        );
+    }
+
+    #[test]
+    fn updates_a_section_in_place() {
+        let directory = std::env::temp_dir().join(format!("nanohue-config-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("config.yml");
+        fs::write(&path, "hue:\n  host: 192.168.1.2\n  group: Living\n").unwrap();
+
+        let path_name = path.to_str().unwrap();
+        update_config(
+            path_name,
+            &["bridges", "upstairs"],
+            vec![
+                ("host", String::from("192.168.1.3")),
+                ("username", String::from("def")),
+            ],
+        )
+        .unwrap();
+        update_config(path_name, &["hue"], vec![("username", String::from("abc"))]).unwrap();
+
+        let hue: HueConfig = read_section(path_name, "hue").unwrap();
+        assert_eq!(hue.host.as_deref(), Some("192.168.1.2"));
+        assert_eq!(hue.group.as_deref(), Some("Living"));
+        assert_eq!(hue.username, "abc");
+
+        let bridges: BTreeMap<String, HueConfig> = read_section(path_name, "bridges").unwrap();
+        assert_eq!(bridges["upstairs"].host.as_deref(), Some("192.168.1.3"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Nothing is left behind next to it.
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,342 @@
+use std::{collections::HashSet, fmt, net::SocketAddr, time::Duration};
+
+use futures_util::future::join_all;
+use log::{trace, warn};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use reqwest::Url;
+use serde::Serialize;
+use tokio::{
+    net::UdpSocket,
+    time::{timeout_at, Instant},
+};
+
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const NANOLEAF_SEARCH_TARGETS: [&str; 2] = ["nanoleaf_aurora:light", "nanoleaf:nl29"];
+
+/// How long to browse for when re-resolving a single device.
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    HueBridge,
+    Nanoleaf,
+}
+
+impl DeviceKind {
+    fn service_type(self) -> &'static str {
+        match self {
+            DeviceKind::HueBridge => "_hue._tcp.local.",
+            DeviceKind::Nanoleaf => "_nanoleafapi._tcp.local.",
+        }
+    }
+
+    /// TXT record keys holding the device's unique ID and model.
+    fn txt_keys(self) -> (&'static str, &'static str) {
+        match self {
+            DeviceKind::HueBridge => ("bridgeid", "modelid"),
+            DeviceKind::Nanoleaf => ("id", "md"),
+        }
+    }
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceKind::HueBridge => write!(f, "Hue bridge"),
+            DeviceKind::Nanoleaf => write!(f, "Nanoleaf"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredDevice {
+    pub kind: DeviceKind,
+    pub id: String,
+    pub name: String,
+    pub host: String,
+    pub model: Option<String>,
+}
+
+/// Browse the local network for the given kinds of devices. mDNS is used for
+/// everything, with SSDP as a fallback for Nanoleaf controllers that don't
+/// advertise themselves over mDNS. Failures are logged rather than returned,
+/// since an empty result is the natural outcome of a failed search anyway.
+pub async fn discover(kinds: &[DeviceKind], timeout: Duration) -> Vec<DiscoveredDevice> {
+    let deadline = Instant::now() + timeout;
+
+    let mdns = browse_mdns(kinds, deadline);
+    let ssdp = async {
+        if !kinds.contains(&DeviceKind::Nanoleaf) {
+            return Vec::new();
+        }
+
+        match search_ssdp(deadline).await {
+            Ok(devices) => devices,
+            Err(err) => {
+                warn!(target: "discovery", "SSDP search failed. {}", err);
+                Vec::new()
+            }
+        }
+    };
+
+    let (mdns, ssdp) = tokio::join!(mdns, ssdp);
+
+    let mut seen = HashSet::new();
+    mdns.into_iter()
+        .chain(ssdp)
+        .filter(|device| seen.insert((device.kind, device.id.to_lowercase())))
+        .collect()
+}
+
+/// Find the current address of a device given its ID.
+pub async fn resolve(kind: DeviceKind, id: &str) -> Option<String> {
+    discover(&[kind], RESOLVE_TIMEOUT)
+        .await
+        .into_iter()
+        .find(|device| device.id.eq_ignore_ascii_case(id))
+        .map(|device| device.host)
+}
+
+/// Work out which host to talk to from a configured host and/or device ID. A
+/// device ID takes precedence, with the host acting as a fallback when the
+/// device can't be found on the network.
+pub async fn resolve_host(
+    kind: DeviceKind,
+    host: Option<&str>,
+    id: Option<&str>,
+) -> Result<String, String> {
+    let Some(id) = id else {
+        return host
+            .map(String::from)
+            .ok_or_else(|| format!("no host or device ID configured for the {}", kind));
+    };
+
+    if let Some(resolved) = resolve(kind, id).await {
+        trace!(target: "discovery", "Resolved {} {} to {}.", kind, id, resolved);
+        return Ok(resolved);
+    }
+
+    match host {
+        Some(host) => {
+            warn!(
+                target: "discovery",
+                "Unable to find {} {} on the network. Falling back to {}.",
+                kind,
+                id,
+                host
+            );
+            Ok(host.to_string())
+        }
+        None => Err(format!("unable to find {} {} on the network", kind, id)),
+    }
+}
+
+async fn browse_mdns(kinds: &[DeviceKind], deadline: Instant) -> Vec<DiscoveredDevice> {
+    let daemon = match ServiceDaemon::new() {
+        Ok(daemon) => daemon,
+        Err(err) => {
+            warn!(target: "discovery", "Unable to start mDNS browsing. {}", err);
+            return Vec::new();
+        }
+    };
+
+    let browsers = kinds
+        .iter()
+        .filter_map(|&kind| match daemon.browse(kind.service_type()) {
+            Ok(receiver) => Some((kind, receiver)),
+            Err(err) => {
+                warn!(target: "discovery", "Unable to browse for {}. {}", kind, err);
+                None
+            }
+        });
+
+    let results = join_all(browsers.map(|(kind, receiver)| async move {
+        let mut devices = Vec::new();
+
+        while let Ok(Ok(event)) = timeout_at(deadline, receiver.recv_async()).await {
+            if let ServiceEvent::ServiceResolved(info) = event {
+                trace!(target: "discovery", "Resolved mDNS service {:?}", info.get_fullname());
+                devices.extend(device_from_service(kind, &info));
+            }
+        }
+
+        devices
+    }))
+    .await;
+
+    let _ = daemon.shutdown();
+
+    results.into_iter().flatten().collect()
+}
+
+fn device_from_service(kind: DeviceKind, info: &ServiceInfo) -> Option<DiscoveredDevice> {
+    let (id_key, model_key) = kind.txt_keys();
+
+    let addresses = info.get_addresses();
+    let address = addresses
+        .iter()
+        .find(|address| address.is_ipv4())
+        .or_else(|| addresses.iter().next())?;
+
+    let name = info
+        .get_fullname()
+        .strip_suffix(info.get_type())
+        .unwrap_or(info.get_fullname())
+        .trim_end_matches('.');
+
+    Some(DiscoveredDevice {
+        kind,
+        id: info.get_property_val_str(id_key)?.to_string(),
+        name: name.to_string(),
+        host: address.to_string(),
+        model: info.get_property_val_str(model_key).map(String::from),
+    })
+}
+
+async fn search_ssdp(deadline: Instant) -> std::io::Result<Vec<DiscoveredDevice>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+
+    for target in NANOLEAF_SEARCH_TARGETS {
+        let request = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+            SSDP_ADDRESS, target
+        );
+        socket.send_to(request.as_bytes(), SSDP_ADDRESS).await?;
+    }
+
+    let mut devices = Vec::new();
+    let mut buffer = [0_u8; 2048];
+
+    while let Ok(received) = timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+        let (length, source) = received?;
+        let response = String::from_utf8_lossy(&buffer[..length]);
+        trace!(target: "discovery", "SSDP response from {}: {:?}", source, response);
+
+        devices.extend(parse_ssdp_response(&response, source));
+    }
+
+    Ok(devices)
+}
+
+fn parse_ssdp_response(response: &str, source: SocketAddr) -> Option<DiscoveredDevice> {
+    let header = |name: &str| {
+        response.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then(|| value.trim().to_string())
+        })
+    };
+
+    let search_target = header("st")?;
+    if !NANOLEAF_SEARCH_TARGETS.contains(&search_target.as_str()) {
+        return None;
+    }
+
+    let host = header("location")
+        .and_then(|location| Url::parse(&location).ok())
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_else(|| source.ip().to_string());
+
+    Some(DiscoveredDevice {
+        kind: DeviceKind::Nanoleaf,
+        id: header("nl-deviceid")?,
+        name: header("nl-devicename").unwrap_or_default(),
+        host,
+        model: Some(search_target),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SSDP_RESPONSE: &str = include_str!("../tests/fixtures/nanoleaf/ssdp_response.txt");
+
+    fn source() -> SocketAddr {
+        "192.168.1.42:1900".parse().unwrap()
+    }
+
+    #[test]
+    fn parses_an_ssdp_response() {
+        let device = parse_ssdp_response(SSDP_RESPONSE, source()).unwrap();
+
+        assert_eq!(device.kind, DeviceKind::Nanoleaf);
+        assert_eq!(device.id, "5E:2F:1A:4B:9C:77");
+        assert_eq!(device.name, "Canvas 9C77");
+        assert_eq!(device.host, "192.168.1.42");
+        assert_eq!(device.model.as_deref(), Some("nanoleaf:nl29"));
+    }
+
+    #[test]
+    fn falls_back_to_the_ssdp_sender_without_a_location() {
+        let response = SSDP_RESPONSE.replace("Location: http://192.168.1.42:16021\r\n", "");
+        let source = "192.168.1.77:1900".parse().unwrap();
+
+        let device = parse_ssdp_response(&response, source).unwrap();
+        assert_eq!(device.host, "192.168.1.77");
+    }
+
+    #[test]
+    fn ignores_other_ssdp_responses() {
+        let other = SSDP_RESPONSE.replace("ST: nanoleaf:nl29", "ST: upnp:rootdevice");
+        assert!(parse_ssdp_response(&other, source()).is_none());
+
+        let anonymous = SSDP_RESPONSE.replace("nl-deviceid: 5E:2F:1A:4B:9C:77\r\n", "");
+        assert!(parse_ssdp_response(&anonymous, source()).is_none());
+    }
+
+    #[test]
+    fn turns_mdns_services_into_devices() {
+        let nanoleaf = ServiceInfo::new(
+            DeviceKind::Nanoleaf.service_type(),
+            "Shapes 4B2D",
+            "Shapes-4B2D.local.",
+            "192.168.1.43",
+            16021,
+            &[
+                ("md", "NL42"),
+                ("srcvers", "9.2.4"),
+                ("id", "7A:11:C3:9E:4B:2D"),
+            ][..],
+        )
+        .unwrap();
+        let device = device_from_service(DeviceKind::Nanoleaf, &nanoleaf).unwrap();
+        assert_eq!(device.kind, DeviceKind::Nanoleaf);
+        assert_eq!(device.id, "7A:11:C3:9E:4B:2D");
+        assert_eq!(device.name, "Shapes 4B2D");
+        assert_eq!(device.host, "192.168.1.43");
+        assert_eq!(device.model.as_deref(), Some("NL42"));
+
+        let bridge = ServiceInfo::new(
+            DeviceKind::HueBridge.service_type(),
+            "Hue Bridge - 4A1F2E",
+            "001788fffe4a1f2e.local.",
+            "192.168.1.10",
+            443,
+            &[("bridgeid", "001788fffe4a1f2e"), ("modelid", "BSB002")][..],
+        )
+        .unwrap();
+        let device = device_from_service(DeviceKind::HueBridge, &bridge).unwrap();
+        assert_eq!(device.id, "001788fffe4a1f2e");
+        assert_eq!(device.name, "Hue Bridge - 4A1F2E");
+        assert_eq!(device.host, "192.168.1.10");
+        assert_eq!(device.model.as_deref(), Some("BSB002"));
+    }
+
+    #[test]
+    fn skips_mdns_services_without_an_id() {
+        let service = ServiceInfo::new(
+            DeviceKind::Nanoleaf.service_type(),
+            "Shapes 4B2D",
+            "Shapes-4B2D.local.",
+            "192.168.1.43",
+            16021,
+            &[("md", "NL42"), ("srcvers", "9.2.4")][..],
+        )
+        .unwrap();
+
+        assert!(device_from_service(DeviceKind::Nanoleaf, &service).is_none());
+    }
+}
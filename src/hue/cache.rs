@@ -0,0 +1,589 @@
+use log::{trace, warn};
+
+use super::{
+    client::Hue,
+    error::HueError,
+    types::{
+        Device, EntertainmentConfiguration, EventKind, EventMessage, GroupedLight, Light,
+        ResourceDelta, Room, Scene, Zone,
+    },
+};
+
+/// Resources that can be looked up by their ID.
+trait Identified {
+    fn id(&self) -> &str;
+}
+
+macro_rules! identified {
+    ($($resource:ty),*) => {
+        $(impl Identified for $resource {
+            fn id(&self) -> &str {
+                &self.id
+            }
+        })*
+    };
+}
+
+identified!(
+    Light,
+    Room,
+    GroupedLight,
+    Scene,
+    Device,
+    EntertainmentConfiguration
+);
+
+fn find<'a, T: Identified>(resources: &'a [T], id: &str) -> Option<&'a T> {
+    resources.iter().find(|resource| resource.id() == id)
+}
+
+fn find_mut<'a, T: Identified>(resources: &'a mut [T], id: &str) -> Option<&'a mut T> {
+    resources.iter_mut().find(|resource| resource.id() == id)
+}
+
+fn upsert<T: Identified>(resources: &mut Vec<T>, resource: T) {
+    match resources
+        .iter_mut()
+        .find(|existing| existing.id() == resource.id())
+    {
+        Some(existing) => *existing = resource,
+        None => resources.push(resource),
+    }
+}
+
+fn remove<T: Identified>(resources: &mut Vec<T>, id: &str) {
+    resources.retain(|resource| resource.id() != id);
+}
+
+/// A local copy of the bridge's resources, loaded once and then kept up to
+/// date from the event stream. Resources keep the order the bridge lists them
+/// in, so anything built from them comes out the same way every time.
+#[derive(Debug, Default)]
+pub struct ResourceCache {
+    lights: Vec<Light>,
+    rooms: Vec<Room>,
+    zones: Vec<Zone>,
+    scenes: Vec<Scene>,
+    grouped_lights: Vec<GroupedLight>,
+    devices: Vec<Device>,
+    entertainment_configurations: Vec<EntertainmentConfiguration>,
+}
+
+impl ResourceCache {
+    pub async fn load(hue: &Hue) -> Result<ResourceCache, HueError> {
+        let (lights, rooms, zones, scenes, grouped_lights, devices, entertainment_configurations) =
+            tokio::try_join!(
+                hue.lights(),
+                hue.rooms(),
+                hue.zones(),
+                hue.scenes(),
+                hue.groups(),
+                hue.devices(),
+                hue.entertainment_configurations()
+            )?;
+
+        Ok(ResourceCache {
+            lights,
+            rooms,
+            zones,
+            scenes,
+            grouped_lights,
+            devices,
+            entertainment_configurations,
+        })
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn light(&self, id: &str) -> Option<&Light> {
+        find(&self.lights, id)
+    }
+
+    pub fn rooms(&self) -> &[Room] {
+        &self.rooms
+    }
+
+    pub fn room(&self, id: &str) -> Option<&Room> {
+        find(&self.rooms, id)
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    pub fn zone(&self, id: &str) -> Option<&Zone> {
+        find(&self.zones, id)
+    }
+
+    pub fn scenes(&self) -> &[Scene] {
+        &self.scenes
+    }
+
+    pub fn scene(&self, id: &str) -> Option<&Scene> {
+        find(&self.scenes, id)
+    }
+
+    pub fn grouped_light(&self, id: &str) -> Option<&GroupedLight> {
+        find(&self.grouped_lights, id)
+    }
+
+    pub fn entertainment_configurations(&self) -> &[EntertainmentConfiguration] {
+        &self.entertainment_configurations
+    }
+
+    pub fn entertainment_configuration(&self, id: &str) -> Option<&EntertainmentConfiguration> {
+        find(&self.entertainment_configurations, id)
+    }
+
+    /// Replace every light, e.g. after sampling them all at once.
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        self.lights = lights;
+    }
+
+    /// Apply a message from the event stream. Updates are applied in place,
+    /// since they carry everything that changed. Added resources, and any
+    /// update to a resource we haven't seen, are fetched instead.
+    pub async fn apply(&mut self, hue: &Hue, kind: EventKind, message: &EventMessage) {
+        let id = message.id.as_str();
+        let resource_type = message.resource_type();
+
+        match kind {
+            EventKind::Delete => {
+                trace!(target: "hue", "Removing {} {} from the cache.", resource_type, id);
+                match message.delta {
+                    ResourceDelta::Light(_) => remove(&mut self.lights, id),
+                    ResourceDelta::Room(_) => remove(&mut self.rooms, id),
+                    ResourceDelta::Zone(_) => remove(&mut self.zones, id),
+                    ResourceDelta::Scene(_) => remove(&mut self.scenes, id),
+                    ResourceDelta::GroupedLight(_) => remove(&mut self.grouped_lights, id),
+                    ResourceDelta::Device(_) => remove(&mut self.devices, id),
+                    ResourceDelta::EntertainmentConfiguration(_) => {
+                        remove(&mut self.entertainment_configurations, id)
+                    }
+                    ResourceDelta::Other => {}
+                }
+                return;
+            }
+            EventKind::Error => return,
+            EventKind::Update if self.update(message) => return,
+            EventKind::Update | EventKind::Add => {}
+        }
+
+        trace!(target: "hue", "Fetching {} {} for the cache.", resource_type, id);
+        if let Err(err) = self.refresh(hue, &message.delta, id).await {
+            warn!(target: "hue", "Unable to refresh {} {}. {}", resource_type, id, err);
+        }
+    }
+
+    /// Apply an update in place, returning false if the resource isn't in the
+    /// cache and needs to be fetched instead.
+    fn update(&mut self, message: &EventMessage) -> bool {
+        let id = message.id.as_str();
+
+        match &message.delta {
+            ResourceDelta::Light(delta) => {
+                let Some(light) = find_mut(&mut self.lights, id) else {
+                    return false;
+                };
+
+                if let Some(name) = delta.metadata.as_ref().and_then(|m| m.name.clone()) {
+                    light.metadata.name = name;
+                }
+                if let Some(on) = &delta.on {
+                    light.on = on.clone();
+                }
+                if let Some(dimming) = &delta.dimming {
+                    light.dimming = Some(dimming.clone());
+                }
+                // Color updates only carry the new coordinates.
+                if let (Some(color), Some(update)) = (&mut light.color, &delta.color) {
+                    color.xy = update.xy;
+                }
+                if let Some(color_temperature) = &delta.color_temperature {
+                    light.color_temperature = Some(color_temperature.clone());
+                }
+                if let Some(dynamics) = &delta.dynamics {
+                    if let Some(status) = &dynamics.status {
+                        light.dynamics.status = status.clone();
+                    }
+                    if let Some(speed) = dynamics.speed {
+                        light.dynamics.speed = speed;
+                    }
+                    if let Some(speed_valid) = dynamics.speed_valid {
+                        light.dynamics.speed_valid = speed_valid;
+                    }
+                }
+                if let Some(gradient) = &delta.gradient {
+                    light.gradient = Some(gradient.clone());
+                }
+                if let (Some(effects), Some(status)) = (
+                    &mut light.effects,
+                    delta.effects.as_ref().and_then(|e| e.status.as_ref()),
+                ) {
+                    effects.status = status.clone();
+                }
+                true
+            }
+            ResourceDelta::GroupedLight(delta) => {
+                let Some(group) = find_mut(&mut self.grouped_lights, id) else {
+                    return false;
+                };
+
+                if let Some(on) = &delta.on {
+                    group.on = on.clone();
+                }
+                if let Some(dimming) = &delta.dimming {
+                    group.dimming = Some(dimming.clone());
+                }
+                true
+            }
+            ResourceDelta::Scene(delta) => {
+                let Some(scene) = find_mut(&mut self.scenes, id) else {
+                    return false;
+                };
+
+                if let Some(name) = delta.metadata.as_ref().and_then(|m| m.name.clone()) {
+                    scene.metadata.name = name;
+                }
+                if let Some(status) = &delta.status {
+                    scene.status = status.clone();
+                }
+                if let Some(palette) = &delta.palette {
+                    scene.palette = palette.clone();
+                }
+                if let Some(actions) = &delta.actions {
+                    scene.actions = actions.clone();
+                }
+                true
+            }
+            ResourceDelta::Room(delta) | ResourceDelta::Zone(delta) => {
+                let groups = match message.delta {
+                    ResourceDelta::Zone(_) => &mut self.zones,
+                    _ => &mut self.rooms,
+                };
+                let Some(group) = find_mut(groups, id) else {
+                    return false;
+                };
+
+                if let Some(name) = delta.metadata.as_ref().and_then(|m| m.name.clone()) {
+                    group.metadata.name = name;
+                }
+                if let Some(children) = &delta.children {
+                    group.children = children.clone();
+                }
+                if let Some(services) = &delta.services {
+                    group.services = services.clone();
+                }
+                true
+            }
+            ResourceDelta::Device(delta) => {
+                let Some(device) = find_mut(&mut self.devices, id) else {
+                    return false;
+                };
+
+                if let Some(name) = delta.metadata.as_ref().and_then(|m| m.name.clone()) {
+                    device.metadata.name = name;
+                }
+                if let Some(services) = &delta.services {
+                    device.services = services.clone();
+                }
+                true
+            }
+            ResourceDelta::EntertainmentConfiguration(delta) => {
+                let Some(area) = find_mut(&mut self.entertainment_configurations, id) else {
+                    return false;
+                };
+
+                if let Some(name) = delta.metadata.as_ref().and_then(|m| m.name.clone()) {
+                    area.metadata.name = name;
+                }
+                if let Some(status) = &delta.status {
+                    area.status = status.clone();
+                    // The streamer is only listed while the area is active.
+                    area.active_streamer = delta.active_streamer.clone();
+                }
+                true
+            }
+            // Nothing to keep track of.
+            ResourceDelta::Other => true,
+        }
+    }
+
+    async fn refresh(
+        &mut self,
+        hue: &Hue,
+        delta: &ResourceDelta,
+        id: &str,
+    ) -> Result<(), HueError> {
+        match delta {
+            ResourceDelta::Light(_) => upsert(&mut self.lights, hue.light(id).await?),
+            ResourceDelta::Room(_) => upsert(&mut self.rooms, hue.room(id).await?),
+            ResourceDelta::Zone(_) => upsert(&mut self.zones, hue.zone(id).await?),
+            ResourceDelta::Scene(_) => upsert(&mut self.scenes, hue.scene(id).await?),
+            ResourceDelta::GroupedLight(_) => {
+                upsert(&mut self.grouped_lights, hue.group(id).await?)
+            }
+            ResourceDelta::Device(_) => upsert(&mut self.devices, hue.device(id).await?),
+            ResourceDelta::EntertainmentConfiguration(_) => upsert(
+                &mut self.entertainment_configurations,
+                hue.entertainment_configuration(id).await?,
+            ),
+            ResourceDelta::Other => return Ok(()),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hue::types::{Dimming, Event, GroupedLightDelta, HueResponse},
+        testing::{Reply, StandIn},
+    };
+
+    const PLUG: &str = include_str!("../../tests/fixtures/hue/plug.json");
+    const GROUPED_LIGHTS: &str = include_str!("../../tests/fixtures/hue/grouped_lights.json");
+    const LIGHTS: &str = include_str!("../../tests/fixtures/hue/lights.json");
+    const ROOMS: &str = include_str!("../../tests/fixtures/hue/rooms.json");
+    const ZONES: &str = include_str!("../../tests/fixtures/hue/zones.json");
+    const SCENES: &str = include_str!("../../tests/fixtures/hue/scenes.json");
+    const SCENE_LIGHTS: &str = include_str!("../../tests/fixtures/hue/scene_lights.json");
+    const EVENTS: &str = include_str!("../../tests/fixtures/hue/events.json");
+
+    /// A stand-in bridge that lists the recorded resources, and serves the
+    /// lights in recorded scenes one at a time as if they had just been added.
+    async fn recorded_bridge() -> (StandIn, Hue) {
+        let bridge = StandIn::start(|request| {
+            let list = match request.path.as_str() {
+                "/clip/v2/resource/light" => LIGHTS,
+                "/clip/v2/resource/room" => ROOMS,
+                "/clip/v2/resource/zone" => ZONES,
+                "/clip/v2/resource/scene" => SCENES,
+                "/clip/v2/resource/grouped_light" => GROUPED_LIGHTS,
+                path => {
+                    let Some(id) = path.strip_prefix("/clip/v2/resource/light/") else {
+                        return Reply::Json(200, String::from(r#"{"errors":[],"data":[]}"#));
+                    };
+                    let lights: serde_json::Value = serde_json::from_str(SCENE_LIGHTS).unwrap();
+                    let found = lights["data"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .find(|light| light["id"] == id);
+
+                    return match found {
+                        Some(light) => Reply::Json(
+                            200,
+                            serde_json::json!({ "errors": [], "data": [light] }).to_string(),
+                        ),
+                        None => Reply::status(404),
+                    };
+                }
+            };
+            Reply::Json(200, list.to_string())
+        })
+        .await;
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+
+        (bridge, hue)
+    }
+
+    fn message(json: &str) -> EventMessage {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn applies_updates_in_place() {
+        let (bridge, hue) = recorded_bridge().await;
+        let mut cache = ResourceCache::load(&hue).await.unwrap();
+        let loaded = bridge.requests().len();
+
+        // The light and its room's grouped light changed with a new scene.
+        let events: Vec<Event> = serde_json::from_str(EVENTS).unwrap();
+        for message in &events[0].data[..2] {
+            cache.apply(&hue, EventKind::Update, message).await;
+        }
+
+        let light = cache.light("3a6710fa-4474-4eba-b533-5e6e72968feb").unwrap();
+        assert_eq!(light.brightness(), 64.43);
+        assert_eq!(light.dynamics.status, "dynamic_palette");
+        assert_eq!(light.dynamics.speed, 0.6);
+        let color = light.color.as_ref().unwrap();
+        assert_eq!((color.xy.x, color.xy.y), (0.4573, 0.41));
+        // Only the coordinates are sent, so the gamut stays as it was.
+        assert!(color.gamut.is_some());
+
+        let group = cache
+            .grouped_light("6d2f9b1e-3c4a-4f8b-a0e7-5b9c2d1f8e36")
+            .unwrap();
+        assert_eq!(group.brightness(), 64.43);
+
+        let updates = [
+            r#"{"id":"4e6a8c0b-2d4f-4a6c-8e0b-1d3f5a7c9e42","metadata":{"name":"Evening"},"status":{"active":"dynamic_palette"},"type":"scene"}"#,
+            r#"{"id":"a5c3e1f7-9b2d-4e6a-8c0f-1d7b3e5a9c24","metadata":{"name":"Lounge"},"type":"room"}"#,
+            r#"{"id":"f7a9c1e3-5b2d-4c8a-9e6f-2a4c6e8b0d15","children":[{"rid":"d4c1a6f0-3b7e-4e52-8a9d-61f0e2c8b5a7","rtype":"light"}],"type":"zone"}"#,
+        ];
+        for update in updates {
+            cache.apply(&hue, EventKind::Update, &message(update)).await;
+        }
+
+        let scene = cache.scene("4e6a8c0b-2d4f-4a6c-8e0b-1d3f5a7c9e42").unwrap();
+        assert_eq!(scene.metadata.name, "Evening");
+        assert_eq!(scene.status.active, "dynamic_palette");
+        assert_eq!(scene.actions.len(), 3);
+
+        let room = cache.room("a5c3e1f7-9b2d-4e6a-8c0f-1d7b3e5a9c24").unwrap();
+        assert_eq!(room.metadata.name, "Lounge");
+        assert_eq!(room.children.len(), 2);
+
+        let zone = cache.zone("f7a9c1e3-5b2d-4c8a-9e6f-2a4c6e8b0d15").unwrap();
+        assert_eq!(zone.metadata.name, "TV");
+        assert!(zone
+            .children
+            .iter()
+            .map(|child| child.id.as_str())
+            .eq(["d4c1a6f0-3b7e-4e52-8a9d-61f0e2c8b5a7"]));
+
+        // Buttons and sensors are of no interest.
+        for message in &events[1].data {
+            cache.apply(&hue, EventKind::Update, message).await;
+        }
+
+        // Everything was known already, so nothing had to be fetched.
+        assert_eq!(bridge.requests().len(), loaded);
+    }
+
+    #[tokio::test]
+    async fn removes_deleted_resources() {
+        let (bridge, hue) = recorded_bridge().await;
+        let mut cache = ResourceCache::load(&hue).await.unwrap();
+        let loaded = bridge.requests().len();
+
+        let deleted = [
+            r#"{"id":"d4c1a6f0-3b7e-4e52-8a9d-61f0e2c8b5a7","type":"light"}"#,
+            r#"{"id":"f7a9c1e3-5b2d-4c8a-9e6f-2a4c6e8b0d15","type":"zone"}"#,
+        ];
+        for delete in deleted {
+            cache.apply(&hue, EventKind::Delete, &message(delete)).await;
+        }
+
+        assert!(cache
+            .light("d4c1a6f0-3b7e-4e52-8a9d-61f0e2c8b5a7")
+            .is_none());
+        assert_eq!(cache.lights().len(), 2);
+        assert!(cache.zones().is_empty());
+        assert_eq!(bridge.requests().len(), loaded);
+    }
+
+    #[tokio::test]
+    async fn fetches_added_and_unknown_resources() {
+        let (bridge, hue) = recorded_bridge().await;
+        let mut cache = ResourceCache::load(&hue).await.unwrap();
+        let loaded = bridge.requests().len();
+
+        // A new light was paired, and another one we've never seen changed.
+        cache
+            .apply(
+                &hue,
+                EventKind::Add,
+                &message(r#"{"id":"6b8d0f2a-4c6e-4a8b-9d1f-3a5c7e9b1d04","type":"light"}"#),
+            )
+            .await;
+        cache
+            .apply(
+                &hue,
+                EventKind::Update,
+                &message(r#"{"id":"0f2a4c6e-8b0d-4f2a-b4c6-7e9a1c3e5b68","on":{"on":false},"type":"light"}"#),
+            )
+            .await;
+
+        let paths: Vec<String> = bridge.requests()[loaded..]
+            .iter()
+            .map(|request| request.path.clone())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "/clip/v2/resource/light/6b8d0f2a-4c6e-4a8b-9d1f-3a5c7e9b1d04",
+                "/clip/v2/resource/light/0f2a4c6e-8b0d-4f2a-b4c6-7e9a1c3e5b68",
+            ]
+        );
+
+        let names: Vec<&str> = cache
+            .lights()
+            .iter()
+            .map(|light| light.metadata.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["Play bar left", "Desk", "Hallway", "TV strip", "Bloom"]
+        );
+        assert!(cache
+            .light("6b8d0f2a-4c6e-4a8b-9d1f-3a5c7e9b1d04")
+            .unwrap()
+            .gradient
+            .is_some());
+
+        // A resource the bridge no longer has is left out, rather than failing.
+        cache
+            .apply(
+                &hue,
+                EventKind::Update,
+                &message(r#"{"id":"1c3e5a7b-9d0f-4b2c-8e4a-6c8e0a2c4e97","on":{"on":true},"type":"light"}"#),
+            )
+            .await;
+        assert_eq!(cache.lights().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn loads_a_bridge_with_plugs() {
+        let bridge = StandIn::start(|request| {
+            let body = match request.path.as_str() {
+                "/clip/v2/resource/light" => PLUG,
+                "/clip/v2/resource/grouped_light" => GROUPED_LIGHTS,
+                _ => r#"{"errors":[],"data":[]}"#,
+            };
+            Reply::Json(200, body.to_string())
+        })
+        .await;
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+
+        let cache = ResourceCache::load(&hue).await.unwrap();
+
+        assert_eq!(cache.lights().len(), 1);
+        assert!(cache.lights()[0].dimming.is_none());
+        assert_eq!(cache.grouped_lights.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn picks_up_dimming_on_groups_that_had_none() {
+        let mut cache = ResourceCache {
+            grouped_lights: serde_json::from_str::<HueResponse<GroupedLight>>(GROUPED_LIGHTS)
+                .unwrap()
+                .data,
+            ..ResourceCache::default()
+        };
+        let hue = Hue::new(String::from("bridge"), String::from("key")).unwrap();
+
+        // A dimmable light was added to the room.
+        let message = EventMessage {
+            id: String::from("0e2a4c6b-8d1f-4a3e-b5c7-9d1f3a5c7e28"),
+            owner: None,
+            delta: ResourceDelta::GroupedLight(GroupedLightDelta {
+                on: None,
+                dimming: Some(Dimming { brightness: 20.0 }),
+            }),
+        };
+        cache.apply(&hue, EventKind::Update, &message).await;
+
+        assert_eq!(cache.grouped_lights[1].brightness(), 20.0);
+    }
+}
@@ -1,19 +1,126 @@
-use log::trace;
-use reqwest::Response;
+use std::{ops::RangeInclusive, sync::RwLock, time::Duration};
 
-use super::types::{Event, GroupedLight, HueResponse, Light, Room, Scene};
+use log::{info, trace};
+use reqwest::{Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{sync::Mutex, time::Instant};
+
+use super::{
+    error::HueError,
+    eventstream::EventStream,
+    types::{
+        CommandResponse, Credentials, Device, Entertainment, EntertainmentConfiguration,
+        GroupedLight, GroupedLightUpdate, HueResponse, Light, LightUpdate, PairingRequest,
+        PairingResponse, Room, Scene, SceneRecall, SceneUpdate, Zone,
+    },
+};
+
+/// Philips recommends sending lights no more than ten commands a second, or
+/// the bridge starts dropping them.
+const COMMAND_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Commands to a grouped light fan out to every light in it, so Philips
+/// recommends sending them no more than once a second.
+const GROUP_COMMAND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The brightness lights accept, as a percentage.
+const BRIGHTNESS_RANGE: RangeInclusive<f32> = 0.0..=100.0;
+
+/// The color temperatures lights accept, in mirek. Lights with a narrower
+/// range clamp to it themselves.
+const MIREK_RANGE: RangeInclusive<u32> = 153..=500;
+
+/// The range of each CIE xy coordinate.
+const XY_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+
+/// How fast a dynamic scene plays.
+const SPEED_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+
+fn check_range(
+    name: &'static str,
+    value: f32,
+    range: &RangeInclusive<f32>,
+) -> Result<(), HueError> {
+    if range.contains(&value) {
+        return Ok(());
+    }
+
+    Err(HueError::OutOfRange {
+        name,
+        value,
+        min: *range.start(),
+        max: *range.end(),
+    })
+}
+
+/// Check an update before sending it, since the bridge's own errors don't say
+/// which value was wrong.
+fn check_update(update: &LightUpdate) -> Result<(), HueError> {
+    if let Some(dimming) = &update.dimming {
+        check_range("brightness", dimming.brightness, &BRIGHTNESS_RANGE)?;
+    }
+    if let Some(color_temperature) = &update.color_temperature {
+        let mirek_range = *MIREK_RANGE.start() as f32..=*MIREK_RANGE.end() as f32;
+        check_range("mirek", color_temperature.mirek as f32, &mirek_range)?;
+    }
+    if let Some(color) = &update.color {
+        check_range("x", color.xy.x, &XY_RANGE)?;
+        check_range("y", color.xy.y, &XY_RANGE)?;
+    }
+    if let Some(speed) = update.dynamics.as_ref().and_then(|dynamics| dynamics.speed) {
+        check_range("speed", speed, &SPEED_RANGE)?;
+    }
+
+    Ok(())
+}
+
+/// Spaces commands out, so that bursts of them are queued up here rather
+/// than dropped by the bridge.
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(interval: Duration) -> RateLimiter {
+        RateLimiter {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for the next free slot. Slots are handed out in order, so the
+    /// lock is only held long enough to claim one.
+    async fn wait(&self) {
+        let at = {
+            let mut next = self.next.lock().await;
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+
+        tokio::time::sleep_until(at).await;
+    }
+}
 
 pub struct Hue {
     // username: String,
     client_key: String,
-    v2_url: String,
+    v2_url: RwLock<String>,
     client: reqwest::Client,
+    commands: RateLimiter,
+    /// Grouped light commands wait for both limiters.
+    group_commands: RateLimiter,
+    dry_run: bool,
 }
 
 impl Hue {
-    pub fn new(hostname: String, username: String) -> Result<Hue, reqwest::Error> {
+    pub fn new(hostname: String, username: String) -> Result<Hue, HueError> {
         let client = reqwest::Client::builder()
             .danger_accept_invalid_certs(true)
+            // The event stream can stay quiet for hours, so rely on TCP
+            // keep-alives to notice a bridge that silently went away.
+            .tcp_keepalive(Duration::from_secs(30))
             .build()?;
 
         let v2_url = format!("https://{}", hostname);
@@ -21,11 +128,75 @@ impl Hue {
             // username,
             client_key: username,
             client,
-            v2_url,
+            v2_url: RwLock::new(v2_url),
+            commands: RateLimiter::new(COMMAND_INTERVAL),
+            group_commands: RateLimiter::new(GROUP_COMMAND_INTERVAL),
+            dry_run: false,
         })
     }
 
-    async fn get(&self, url: &str) -> Result<Response, Box<dyn std::error::Error>> {
+    /// Log commands instead of sending them to the bridge.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Hue {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Point the client at a different address, e.g. after the bridge was
+    /// given a new DHCP lease.
+    pub fn set_host(&self, hostname: &str) {
+        *self.v2_url.write().unwrap() = format!("https://{}", hostname);
+    }
+
+    /// Talk to a stand-in bridge over plain HTTP.
+    #[cfg(test)]
+    pub fn with_url(self, url: &str) -> Hue {
+        *self.v2_url.write().unwrap() = url.to_string();
+        self
+    }
+
+    fn v2_url(&self) -> String {
+        self.v2_url.read().unwrap().clone()
+    }
+
+    /// Ask the bridge for a new application key and client key. This only
+    /// succeeds within 30 seconds of the bridge's link button being pressed.
+    pub async fn pair(hostname: &str, device_type: &str) -> Result<Credentials, HueError> {
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()?;
+
+        let url = format!("https://{}/api", hostname);
+        let payload = PairingRequest {
+            device_type: device_type.to_string(),
+            generate_client_key: true,
+        };
+
+        trace!(target: "hue", "POST {:?}", url);
+        let response = client.post(&url).json(&payload).send().await?;
+        let response = check_status(response).await?;
+        let json_response: Vec<PairingResponse> = decode(response).await?;
+
+        match json_response.into_iter().next() {
+            Some(PairingResponse::Success(credentials)) => Ok(credentials),
+            // Error 101 is the bridge's way of saying "link button not pressed".
+            Some(PairingResponse::Error(error)) if error.error_type == 101 => {
+                Err(HueError::LinkButtonNotPressed)
+            }
+            Some(PairingResponse::Error(error)) => Err(HueError::Api {
+                error_type: error.error_type,
+                description: error.description,
+            }),
+            None => Err(HueError::Api {
+                error_type: 0,
+                description: String::from("empty pairing response"),
+            }),
+        }
+    }
+
+    async fn get(&self, url: &str) -> Result<Response, HueError> {
+        // Reads count against the bridge's budget too, and sampling lights
+        // during entertainment streaming would otherwise crowd out commands.
+        self.commands.wait().await;
         trace!(
             target: "hue",
             "GET {:?}",
@@ -45,62 +216,449 @@ impl Hue {
             response.status().to_string()
         );
 
-        Ok(response)
+        check_status(response).await
     }
 
-    pub async fn rooms(&self) -> Result<Vec<Room>, Box<dyn std::error::Error>> {
-        let url = format!("{}/clip/v2/resource/room", self.v2_url);
+    async fn get_resources<T>(&self, resource: &str) -> Result<Vec<T>, HueError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let url = format!("{}/clip/v2/resource/{}", self.v2_url(), resource);
         let response = self.get(&url).await?;
-        let json_response: HueResponse<Room> = response.json::<HueResponse<Room>>().await?;
+        let json_response: HueResponse<T> = decode(response).await?;
 
         Ok(json_response.data)
     }
 
-    pub async fn group(&self, id: &str) -> Result<GroupedLight, Box<dyn std::error::Error>> {
-        let url: String = format!("{}/clip/v2/resource/grouped_light/{}", self.v2_url, id);
-        let response = self.get(&url).await?;
-        let json_response: HueResponse<GroupedLight> =
-            response.json::<HueResponse<GroupedLight>>().await?;
+    async fn get_resource<T>(&self, resource: &'static str, id: &str) -> Result<T, HueError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let url = format!("{}/clip/v2/resource/{}/{}", self.v2_url(), resource, id);
+        let response = self.get(&url).await.map_err(|err| match err {
+            HueError::Status {
+                status: StatusCode::NOT_FOUND,
+                ..
+            } => HueError::NotFound {
+                resource,
+                id: id.to_string(),
+            },
+            err => err,
+        })?;
+        let json_response: HueResponse<T> = decode(response).await?;
+
+        json_response
+            .data
+            .into_iter()
+            .next()
+            .ok_or_else(|| HueError::NotFound {
+                resource,
+                id: id.to_string(),
+            })
+    }
+
+    async fn put<T>(&self, resource: &'static str, id: &str, body: &T) -> Result<(), HueError>
+    where
+        T: Serialize,
+    {
+        let url = format!("{}/clip/v2/resource/{}/{}", self.v2_url(), resource, id);
+
+        if self.dry_run {
+            let body = serde_json::to_string(body)?;
+            info!(target: "hue", "Dry run, skipping PUT {:?} {}", url, body);
+            return Ok(());
+        }
+
+        if resource == "grouped_light" {
+            self.group_commands.wait().await;
+        }
+        self.commands.wait().await;
+        trace!(target: "hue", "PUT {:?} {:?}", url, serde_json::to_string(body));
+
+        let response = self
+            .client
+            .put(&url)
+            .header("hue-application-key", &self.client_key)
+            .json(body)
+            .send()
+            .await?;
+        let response = check_status(response).await.map_err(|err| match err {
+            HueError::Status {
+                status: StatusCode::NOT_FOUND,
+                ..
+            } => HueError::NotFound {
+                resource,
+                id: id.to_string(),
+            },
+            err => err,
+        })?;
 
-        let item = json_response.data.iter().nth(0).unwrap();
+        // Some changes can be applied while others are refused, e.g. a color
+        // sent to a light that only does white.
+        let json_response: CommandResponse = decode(response).await?;
+        if json_response.errors.is_empty() {
+            Ok(())
+        } else {
+            let descriptions: Vec<String> = json_response
+                .errors
+                .into_iter()
+                .map(|error| error.description)
+                .collect();
+            Err(HueError::Rejected(descriptions.join("; ")))
+        }
+    }
 
-        Ok(item.clone())
+    pub async fn rooms(&self) -> Result<Vec<Room>, HueError> {
+        self.get_resources("room").await
     }
 
-    pub async fn lights(&self) -> Result<Vec<Light>, Box<dyn std::error::Error>> {
-        let url = format!("{}/clip/v2/resource/light", self.v2_url);
-        let response = self.get(&url).await?;
-        let json_response: HueResponse<Light> = response.json::<HueResponse<Light>>().await?;
+    pub async fn room(&self, id: &str) -> Result<Room, HueError> {
+        self.get_resource("room", id).await
+    }
 
-        Ok(json_response.data.clone())
+    pub async fn zones(&self) -> Result<Vec<Zone>, HueError> {
+        self.get_resources("zone").await
     }
 
-    pub async fn light(&self, id: &str) -> Result<Light, Box<dyn std::error::Error>> {
-        let url: String = format!("{}/clip/v2/resource/light/{}", self.v2_url, id);
-        let response = self.get(&url).await?;
-        let json_response: HueResponse<Light> = response.json::<HueResponse<Light>>().await?;
+    pub async fn zone(&self, id: &str) -> Result<Zone, HueError> {
+        self.get_resource("zone", id).await
+    }
 
-        let item = json_response.data.iter().nth(0).unwrap();
+    pub async fn groups(&self) -> Result<Vec<GroupedLight>, HueError> {
+        self.get_resources("grouped_light").await
+    }
 
-        Ok(item.clone())
+    pub async fn group(&self, id: &str) -> Result<GroupedLight, HueError> {
+        self.get_resource("grouped_light", id).await
     }
 
-    pub async fn scene(&self, id: &str) -> Result<Scene, Box<dyn std::error::Error>> {
-        let url: String = format!("{}/clip/v2/resource/scene/{}", self.v2_url, id);
-        let response = self.get(&url).await?;
-        let json_response: HueResponse<Scene> = response.json::<HueResponse<Scene>>().await?;
+    pub async fn set_grouped_light(
+        &self,
+        id: &str,
+        update: &GroupedLightUpdate,
+    ) -> Result<(), HueError> {
+        check_update(update)?;
+        self.put("grouped_light", id, update).await
+    }
 
-        let item = json_response.data.iter().nth(0).unwrap();
+    pub async fn lights(&self) -> Result<Vec<Light>, HueError> {
+        self.get_resources("light").await
+    }
 
-        Ok(item.clone())
+    pub async fn light(&self, id: &str) -> Result<Light, HueError> {
+        self.get_resource("light", id).await
     }
 
-    pub async fn get_event_stream(&self) -> Result<Vec<Event>, Box<dyn std::error::Error>> {
-        let url = format!("{}/eventstream/clip/v2", self.v2_url);
-        let response = self.get(&url).await?;
+    pub async fn set_light(&self, id: &str, update: &LightUpdate) -> Result<(), HueError> {
+        check_update(update)?;
+        self.put("light", id, update).await
+    }
+
+    pub async fn devices(&self) -> Result<Vec<Device>, HueError> {
+        self.get_resources("device").await
+    }
+
+    pub async fn device(&self, id: &str) -> Result<Device, HueError> {
+        self.get_resource("device", id).await
+    }
+
+    pub async fn scenes(&self) -> Result<Vec<Scene>, HueError> {
+        self.get_resources("scene").await
+    }
+
+    pub async fn scene(&self, id: &str) -> Result<Scene, HueError> {
+        self.get_resource("scene", id).await
+    }
+
+    pub async fn recall_scene(&self, id: &str, recall: SceneRecall) -> Result<(), HueError> {
+        if let Some(dimming) = &recall.dimming {
+            check_range("brightness", dimming.brightness, &BRIGHTNESS_RANGE)?;
+        }
+
+        self.put("scene", id, &SceneUpdate { recall }).await
+    }
+
+    pub async fn entertainment_configurations(
+        &self,
+    ) -> Result<Vec<EntertainmentConfiguration>, HueError> {
+        self.get_resources("entertainment_configuration").await
+    }
+
+    pub async fn entertainment_configuration(
+        &self,
+        id: &str,
+    ) -> Result<EntertainmentConfiguration, HueError> {
+        self.get_resource("entertainment_configuration", id).await
+    }
+
+    pub async fn entertainment_services(&self) -> Result<Vec<Entertainment>, HueError> {
+        self.get_resources("entertainment").await
+    }
+
+    pub async fn event_stream(&self, last_event_id: Option<&str>) -> Result<EventStream, HueError> {
+        let url = format!("{}/eventstream/clip/v2", self.v2_url());
+        trace!(
+            target: "hue",
+            "Opening event stream {:?} from {:?}",
+            url,
+            last_event_id
+        );
+
+        let mut request = self
+            .client
+            .get(&url)
+            .header("hue-application-key", &self.client_key)
+            .header(reqwest::header::ACCEPT, "text/event-stream");
+
+        if let Some(id) = last_event_id {
+            request = request.header("Last-Event-ID", id);
+        }
+
+        let response = check_status(request.send().await?).await?;
+
+        Ok(EventStream::new(
+            response.bytes_stream(),
+            last_event_id.map(String::from),
+        ))
+    }
+}
+
+async fn check_status(response: Response) -> Result<Response, HueError> {
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(HueError::Unauthorized);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    Err(HueError::Status { status, body })
+}
+
+async fn decode<T>(response: Response) -> Result<T, HueError>
+where
+    T: DeserializeOwned,
+{
+    let body = response.text().await?;
+    Ok(serde_json::from_str::<T>(&body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::ColorCoordinate,
+        hue::types::{
+            ColorTemperature, ColorUpdate, Dimming, DynamicsUpdate, OnStatus, RecallAction,
+        },
+        testing::{Reply, StandIn},
+    };
+
+    const LIGHTS: &str = include_str!("../../tests/fixtures/hue/lights.json");
+
+    #[tokio::test]
+    async fn reads_lights_from_a_recorded_response() {
+        let bridge = StandIn::start(|_| Reply::Json(200, LIGHTS.to_string())).await;
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+
+        let lights = hue.lights().await.unwrap();
+
+        let names: Vec<&str> = lights
+            .iter()
+            .map(|light| light.metadata.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Play bar left", "Desk", "Hallway"]);
+        assert!(lights[0].color.as_ref().unwrap().gamut.is_some());
+        assert_eq!(
+            lights[1].color_temperature.as_ref().unwrap().mirek,
+            Some(366)
+        );
+        assert!(lights[2].color.is_none() && lights[2].color_temperature.is_none());
+
+        let requests = bridge.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/clip/v2/resource/light");
+        assert_eq!(requests[0].header("hue-application-key"), Some("key"));
+    }
+
+    #[tokio::test]
+    async fn sends_grouped_light_commands_at_most_once_a_second() {
+        let bridge =
+            StandIn::start(|_| Reply::Json(200, String::from(r#"{"data":[],"errors":[]}"#))).await;
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+        let update = LightUpdate {
+            on: Some(OnStatus { on: true }),
+            ..LightUpdate::default()
+        };
+
+        let started = Instant::now();
+        let (first, second, light) = tokio::join!(
+            hue.set_grouped_light("group", &update),
+            hue.set_grouped_light("group", &update),
+            async {
+                let result = hue.set_light("light", &update).await;
+                (result, started.elapsed())
+            }
+        );
+        assert!(first.is_ok() && second.is_ok() && light.0.is_ok());
+
+        // The second group command waited a full second for the first.
+        assert!(started.elapsed() >= GROUP_COMMAND_INTERVAL);
+        // Light commands only wait for the shorter interval.
+        assert!(light.1 < GROUP_COMMAND_INTERVAL);
+
+        let requests = bridge.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|request| request.method == "PUT" && request.body == r#"{"on":{"on":true}}"#));
+        assert_eq!(requests[2].path, "/clip/v2/resource/grouped_light/group");
+    }
+
+    #[tokio::test]
+    async fn refuses_values_out_of_range_without_sending_them() {
+        let bridge =
+            StandIn::start(|_| Reply::Json(200, String::from(r#"{"data":[],"errors":[]}"#))).await;
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+
+        let cases = [
+            (
+                "brightness",
+                LightUpdate {
+                    dimming: Some(Dimming { brightness: 101.0 }),
+                    ..LightUpdate::default()
+                },
+            ),
+            (
+                "brightness",
+                LightUpdate {
+                    dimming: Some(Dimming {
+                        brightness: f32::NAN,
+                    }),
+                    ..LightUpdate::default()
+                },
+            ),
+            (
+                "mirek",
+                LightUpdate {
+                    color_temperature: Some(ColorTemperature { mirek: 152 }),
+                    ..LightUpdate::default()
+                },
+            ),
+            (
+                "mirek",
+                LightUpdate {
+                    color_temperature: Some(ColorTemperature { mirek: 501 }),
+                    ..LightUpdate::default()
+                },
+            ),
+            (
+                "y",
+                LightUpdate {
+                    color: Some(ColorUpdate {
+                        xy: ColorCoordinate { x: 0.3, y: -0.1 },
+                    }),
+                    ..LightUpdate::default()
+                },
+            ),
+            (
+                "speed",
+                LightUpdate {
+                    dynamics: Some(DynamicsUpdate {
+                        duration: None,
+                        speed: Some(1.5),
+                    }),
+                    ..LightUpdate::default()
+                },
+            ),
+        ];
+
+        for (expected, update) in &cases {
+            let light = hue.set_light("light", update).await;
+            let group = hue.set_grouped_light("group", update).await;
+
+            for result in [light, group] {
+                match result {
+                    Err(HueError::OutOfRange { name, .. }) => assert_eq!(name, *expected),
+                    result => panic!("expected {} to be out of range, got {:?}", expected, result),
+                }
+            }
+        }
+        assert!(bridge.requests().is_empty());
+
+        // The edges of each range are fine.
+        let update = LightUpdate {
+            dimming: Some(Dimming { brightness: 0.0 }),
+            color_temperature: Some(ColorTemperature { mirek: 500 }),
+            color: Some(ColorUpdate {
+                xy: ColorCoordinate { x: 1.0, y: 0.0 },
+            }),
+            ..LightUpdate::default()
+        };
+        hue.set_light("light", &update).await.unwrap();
+        assert_eq!(bridge.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn recalls_scenes() {
+        let bridge =
+            StandIn::start(|_| Reply::Json(200, String::from(r#"{"data":[],"errors":[]}"#))).await;
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+
+        let too_bright = SceneRecall {
+            dimming: Some(Dimming { brightness: 120.0 }),
+            ..SceneRecall::default()
+        };
+        assert!(matches!(
+            hue.recall_scene("scene", too_bright).await,
+            Err(HueError::OutOfRange {
+                name: "brightness",
+                ..
+            })
+        ));
+        assert!(bridge.requests().is_empty());
+
+        let recall = SceneRecall {
+            action: RecallAction::DynamicPalette,
+            duration: None,
+            dimming: Some(Dimming { brightness: 50.0 }),
+        };
+        hue.recall_scene("scene", recall).await.unwrap();
+
+        let requests = bridge.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(requests[0].path, "/clip/v2/resource/scene/scene");
+        assert_eq!(
+            requests[0].body,
+            r#"{"recall":{"action":"dynamic_palette","dimming":{"brightness":50.0}}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn spaces_out_reads() {
+        let bridge = StandIn::start(|_| Reply::Json(200, LIGHTS.to_string())).await;
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
 
-        let json_response = response.json::<Vec<Event>>().await?;
+        let started = Instant::now();
+        let (first, second, third) = tokio::join!(hue.lights(), hue.lights(), hue.lights());
+        assert!(first.is_ok() && second.is_ok() && third.is_ok());
 
-        Ok(json_response)
+        // The first goes out straight away, and each after it waits its turn.
+        assert!(started.elapsed() >= COMMAND_INTERVAL * 2);
+        assert_eq!(bridge.requests().len(), 3);
     }
 }
@@ -0,0 +1,54 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum HueError {
+    #[error("unable to reach the Hue bridge: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    #[error("the Hue bridge rejected the application key")]
+    Unauthorized,
+
+    #[error("the Hue bridge responded with {status}: {body}")]
+    Status { status: StatusCode, body: String },
+
+    #[error("unable to decode the Hue bridge response: {0}")]
+    Decode(#[from] serde_json::Error),
+
+    #[error("the link button on the Hue bridge has not been pressed")]
+    LinkButtonNotPressed,
+
+    #[error("the Hue bridge returned error {error_type}: {description}")]
+    Api {
+        error_type: u32,
+        description: String,
+    },
+
+    #[error("{resource} {id:?} was not found on the Hue bridge")]
+    NotFound { resource: &'static str, id: String },
+
+    #[error("the Hue bridge rejected the command: {0}")]
+    Rejected(String),
+
+    #[error("{name} must be within {min}..={max}, got {value}")]
+    OutOfRange {
+        name: &'static str,
+        value: f32,
+        min: f32,
+        max: f32,
+    },
+}
+
+impl HueError {
+    /// Whether the request that produced this error is worth repeating later,
+    /// e.g. because the bridge was unreachable or temporarily overloaded.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            HueError::Transport(_) => true,
+            HueError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use log::{trace, warn};
+
+use crate::sse::SseParser;
+
+use super::types::Event;
+
+type ByteStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+/// A long-lived connection to the bridge's `/eventstream/clip/v2` endpoint.
+///
+/// Each SSE frame sent by the bridge carries a JSON array of events, which are
+/// yielded one at a time. The stream ends when the connection is closed or
+/// errors, at which point `last_event_id` can be used to resume.
+pub struct EventStream {
+    body: ByteStream,
+    parser: SseParser,
+    pending: VecDeque<Event>,
+    last_event_id: Option<String>,
+}
+
+impl EventStream {
+    pub fn new<S>(body: S, last_event_id: Option<String>) -> EventStream
+    where
+        S: Stream<Item = reqwest::Result<Bytes>> + Send + 'static,
+    {
+        EventStream {
+            body: Box::pin(body),
+            parser: SseParser::new(),
+            pending: VecDeque::new(),
+            last_event_id,
+        }
+    }
+
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    fn process_chunk(&mut self, chunk: &[u8]) {
+        let frames = self.parser.feed(chunk);
+        if let Some(id) = self.parser.last_event_id() {
+            self.last_event_id = Some(id.to_string());
+        }
+
+        for frame in frames {
+            match serde_json::from_str::<Vec<Event>>(&frame.data) {
+                Ok(events) => self.pending.extend(events),
+                Err(err) => warn!(
+                    target: "hue",
+                    "Unable to parse event stream frame. {:?} {:?}",
+                    err,
+                    frame.data
+                ),
+            }
+        }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+
+            match self.body.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(chunk))) => self.process_chunk(&chunk),
+                Poll::Ready(Some(Err(err))) => {
+                    warn!(target: "hue", "Event stream connection failed. {:?}", err);
+                    return Poll::Ready(None);
+                }
+                Poll::Ready(None) => {
+                    trace!(target: "hue", "Event stream closed by the bridge.");
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use crate::{
+        hue::{client::Hue, types::EventKind},
+        testing::{Reply, StandIn},
+    };
+
+    const UPDATE: &str = r#"[{"creationtime":"2024-01-01T00:00:00Z","id":"e1","type":"update","data":[{"id":"gl1","type":"grouped_light","on":{"on":false}}]}]"#;
+    const DELETE: &str = r#"[{"creationtime":"2024-01-01T00:00:01Z","id":"e2","type":"delete","data":[{"id":"scene1","type":"scene"}]}]"#;
+
+    #[tokio::test]
+    async fn reads_events_from_a_stand_in_bridge() {
+        // The line break after the ID is split across chunks.
+        let first = "id: 1700000001:0\r";
+        let second = format!("\ndata: {}\r\n\r\n: hi\r\n\r\n", UPDATE);
+        let third = format!("id: 1700000002:0\r\ndata: {}\r\n\r\n", DELETE);
+        // An ID on its own still moves the stream along.
+        let fourth = "id: 1700000003:0\r\n\r\n";
+        let bridge =
+            StandIn::start(move |_| Reply::events(&[first, &second, &third, fourth])).await;
+
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+        let mut stream = hue.event_stream(Some("1700000000:0")).await.unwrap();
+
+        let update = stream.next().await.unwrap();
+        assert_eq!(update.kind, EventKind::Update);
+        assert_eq!(update.data[0].id, "gl1");
+        assert_eq!(stream.last_event_id(), Some("1700000001:0"));
+
+        let delete = stream.next().await.unwrap();
+        assert_eq!(delete.kind, EventKind::Delete);
+        assert_eq!(delete.data[0].id, "scene1");
+
+        assert!(stream.next().await.is_none());
+        assert_eq!(stream.last_event_id(), Some("1700000003:0"));
+
+        let requests = bridge.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/eventstream/clip/v2");
+        assert_eq!(requests[0].header("hue-application-key"), Some("key"));
+        assert_eq!(requests[0].header("last-event-id"), Some("1700000000:0"));
+    }
+}
@@ -2,29 +2,134 @@ use serde::{Deserialize, Serialize};
 
 use crate::color::{ColorCoordinate, ColorGamut2};
 
+/// What happened to the resources in an event.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Update,
+    Add,
+    Delete,
+    Error,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    #[serde(rename = "creationtime")]
+    pub creation_time: String,
+
+    pub data: Vec<EventMessage>,
+}
+
+/// A single resource within an event. Updates only carry the fields that
+/// changed, while deletes carry nothing beyond the resource's ID.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EventMessage {
     pub id: String,
+    pub owner: Option<Resource>,
 
+    #[serde(flatten)]
+    pub delta: ResourceDelta,
+}
+
+impl EventMessage {
+    /// The resource type, as the bridge names it.
+    pub fn resource_type(&self) -> &'static str {
+        match self.delta {
+            ResourceDelta::Light(_) => "light",
+            ResourceDelta::GroupedLight(_) => "grouped_light",
+            ResourceDelta::Scene(_) => "scene",
+            ResourceDelta::Room(_) => "room",
+            ResourceDelta::Zone(_) => "zone",
+            ResourceDelta::Device(_) => "device",
+            ResourceDelta::EntertainmentConfiguration(_) => "entertainment_configuration",
+            ResourceDelta::Other => "other",
+        }
+    }
+}
+
+/// The changes to a resource, by its type.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResourceDelta {
+    Light(LightDelta),
+    GroupedLight(GroupedLightDelta),
+    Scene(SceneDelta),
+    Room(GroupDelta),
+    Zone(GroupDelta),
+    Device(DeviceDelta),
+    EntertainmentConfiguration(EntertainmentConfigurationDelta),
+    /// Buttons, sensors, and everything else we don't follow.
+    #[serde(other)]
+    Other,
+}
+
+/// Renames only carry the new name.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct MetadataDelta {
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct LightDelta {
+    pub metadata: Option<MetadataDelta>,
     pub on: Option<OnStatus>,
     pub dimming: Option<Dimming>,
+    /// Only the new coordinates; the gamut never changes.
     pub color: Option<Color>,
-    pub status: Option<SceneStatus>,
+    pub color_temperature: Option<LightColorTemperature>,
+    pub dynamics: Option<DynamicsDelta>,
+    pub gradient: Option<Gradient>,
+    pub effects: Option<EffectsDelta>,
+}
 
-    pub owner: Option<Resource>,
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DynamicsDelta {
+    pub status: Option<String>,
+    pub speed: Option<f32>,
+    pub speed_valid: Option<bool>,
+}
 
-    #[serde(rename = "type")]
-    pub message_type: String,
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct EffectsDelta {
+    pub status: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Event {
-    #[serde(rename = "type")]
-    pub event_type: String,
-    #[serde(rename = "creationtime")]
-    pub creation_time: String,
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct GroupedLightDelta {
+    pub on: Option<OnStatus>,
+    pub dimming: Option<Dimming>,
+}
 
-    pub data: Vec<EventMessage>,
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SceneDelta {
+    pub metadata: Option<MetadataDelta>,
+    pub status: Option<SceneStatus>,
+    pub palette: Option<ScenePalette>,
+    pub actions: Option<Vec<SceneAction>>,
+}
+
+/// Rooms and zones report the same changes.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct GroupDelta {
+    pub metadata: Option<MetadataDelta>,
+    pub children: Option<Vec<Resource>>,
+    pub services: Option<Vec<Resource>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DeviceDelta {
+    pub metadata: Option<MetadataDelta>,
+    pub services: Option<Vec<Resource>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct EntertainmentConfigurationDelta {
+    pub metadata: Option<MetadataDelta>,
+    /// Either `active`, while something is streaming to the area, or `inactive`.
+    pub status: Option<String>,
+    pub active_streamer: Option<Resource>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -35,12 +140,12 @@ pub struct Resource {
     pub resource_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RoomMetadata {
     pub name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Room {
     pub id: String,
     pub children: Vec<Resource>,
@@ -48,6 +153,53 @@ pub struct Room {
     pub metadata: RoomMetadata,
 }
 
+impl Room {
+    /// Whether the light belongs to the room or zone, either directly or
+    /// through the device that owns it.
+    pub fn contains_light(&self, light: &Light) -> bool {
+        self.children
+            .iter()
+            .any(|child| match child.resource_type.as_str() {
+                "light" => child.id == light.id,
+                "device" => child.id == light.owner.id,
+                _ => false,
+            })
+    }
+}
+
+/// Zones share the room schema, but their children are usually lights rather
+/// than devices, and a light can belong to any number of zones.
+pub type Zone = Room;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PairingRequest {
+    #[serde(rename = "devicetype")]
+    pub device_type: String,
+    #[serde(rename = "generateclientkey")]
+    pub generate_client_key: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Credentials {
+    pub username: String,
+    #[serde(rename = "clientkey")]
+    pub client_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiError {
+    #[serde(rename = "type")]
+    pub error_type: u32,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PairingResponse {
+    Success(Credentials),
+    Error(ApiError),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HueResponse<T>
 where
@@ -75,18 +227,43 @@ pub struct Dimming {
 pub struct GroupedLight {
     pub id: String,
     pub on: OnStatus,
-    pub dimming: Dimming,
+    /// Missing when none of the group's lights can be dimmed, e.g. a room
+    /// with only smart plugs.
+    pub dimming: Option<Dimming>,
+}
+
+impl GroupedLight {
+    /// The brightness as a percentage. Groups that can't be dimmed are always
+    /// at full brightness.
+    pub fn brightness(&self) -> f32 {
+        self.dimming
+            .as_ref()
+            .map_or(100.0, |dimming| dimming.brightness)
+    }
 }
 
 // Lighting
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Color {
     pub xy: ColorCoordinate,
     pub gamut: Option<ColorGamut2>,
+    pub gamut_type: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LightColorTemperature {
+    pub mirek: Option<u32>,
+    pub mirek_valid: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LightMetadata {
+    pub name: String,
+    pub archetype: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Dynamics {
     pub status: String,
     pub status_values: Vec<String>,
@@ -94,14 +271,39 @@ pub struct Dynamics {
     pub speed_valid: bool,
 }
 
+/// The built-in effects a light can play, such as `candle` or `fire`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LightEffects {
+    pub status: String,
+    #[serde(default)]
+    pub status_values: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Light {
     pub id: String,
     pub owner: Resource,
+    pub metadata: LightMetadata,
     pub on: OnStatus,
-    pub dimming: Dimming,
+    /// Missing for lights that can only be switched on and off, such as
+    /// smart plugs.
+    pub dimming: Option<Dimming>,
     pub color: Option<Color>,
+    pub color_temperature: Option<LightColorTemperature>,
+    #[serde(default)]
     pub dynamics: Dynamics,
+    pub gradient: Option<Gradient>,
+    pub effects: Option<LightEffects>,
+}
+
+impl Light {
+    /// The brightness as a percentage. Lights that can't be dimmed are always
+    /// at full brightness.
+    pub fn brightness(&self) -> f32 {
+        self.dimming
+            .as_ref()
+            .map_or(100.0, |dimming| dimming.brightness)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -110,9 +312,32 @@ pub struct ScenePaletteColor {
     pub dimming: Dimming,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScenePaletteColorTemperature {
+    pub color_temperature: ColorTemperature,
+    pub dimming: Dimming,
+}
+
+/// The colors a scene is made from. Dynamic scenes cycle through these, rather
+/// than the colors in their actions.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ScenePalette {
     pub color: Vec<ScenePaletteColor>,
+    #[serde(default)]
+    pub dimming: Vec<Dimming>,
+    #[serde(default)]
+    pub color_temperature: Vec<ScenePaletteColorTemperature>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GradientPoint {
+    pub color: Color,
+}
+
+/// The colors along a gradient light strip, from one end to the other.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Gradient {
+    pub points: Vec<GradientPoint>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -122,10 +347,11 @@ pub struct ColorTemperature {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Action {
-    pub on: OnStatus,
-    pub dimming: Dimming,
+    pub on: Option<OnStatus>,
+    pub dimming: Option<Dimming>,
     pub color: Option<Color>,
     pub color_temperature: Option<ColorTemperature>,
+    pub gradient: Option<Gradient>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -134,11 +360,373 @@ pub struct SceneAction {
     pub action: Action,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SceneMetadata {
+    pub name: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Scene {
     pub id: String,
+    pub metadata: SceneMetadata,
     pub group: Resource,
     pub palette: ScenePalette,
     pub status: SceneStatus,
     pub actions: Vec<SceneAction>,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceMetadata {
+    pub name: String,
+    pub archetype: Option<String>,
+}
+
+/// A physical device, such as a bulb or a switch, and the services it offers.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Device {
+    pub id: String,
+    pub metadata: DeviceMetadata,
+    pub services: Vec<Resource>,
+}
+
+// Commands
+
+/// A change of color, given as coordinates within the light's gamut.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ColorUpdate {
+    pub xy: ColorCoordinate,
+}
+
+/// How a change is played out. `duration` is the transition time in
+/// milliseconds, and `speed` sets the pace of a dynamic scene, from 0 to 1.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DynamicsUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f32>,
+}
+
+/// The changes to make to a light. Anything left out stays as it is.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LightUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<OnStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimming: Option<Dimming>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_temperature: Option<ColorTemperature>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynamics: Option<DynamicsUpdate>,
+}
+
+/// Grouped lights take the same changes as a single light, and pass them on
+/// to every light in the room or zone.
+pub type GroupedLightUpdate = LightUpdate;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecallAction {
+    /// Recall the scene as it was saved.
+    #[default]
+    Active,
+    /// Recall the scene and cycle through its palette.
+    DynamicPalette,
+    Static,
+}
+
+/// How to recall a scene. `duration` is the transition time in milliseconds,
+/// and `dimming` overrides the brightness the scene was saved with.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SceneRecall {
+    pub action: RecallAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimming: Option<Dimming>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SceneUpdate {
+    pub recall: SceneRecall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommandError {
+    pub description: String,
+}
+
+/// The bridge's answer to a command: the resources it changed, and anything
+/// it couldn't do.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommandResponse {
+    #[serde(default)]
+    pub data: Vec<Resource>,
+    #[serde(default)]
+    pub errors: Vec<CommandError>,
+}
+
+// Entertainment
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct EntertainmentPosition {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EntertainmentChannelMember {
+    pub service: Resource,
+    pub index: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EntertainmentChannel {
+    pub channel_id: u32,
+    pub position: EntertainmentPosition,
+    pub members: Vec<EntertainmentChannelMember>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EntertainmentConfigurationMetadata {
+    pub name: String,
+}
+
+/// An entertainment area, which apps and sync boxes stream colors to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EntertainmentConfiguration {
+    pub id: String,
+    pub metadata: EntertainmentConfigurationMetadata,
+    pub configuration_type: String,
+    /// Either `active`, while something is streaming to the area, or `inactive`.
+    pub status: String,
+    pub active_streamer: Option<Resource>,
+    pub channels: Vec<EntertainmentChannel>,
+    #[serde(default)]
+    pub light_services: Vec<Resource>,
+}
+
+/// The streaming capabilities of a device, which link the channels of an
+/// entertainment area to the lights that render them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Entertainment {
+    pub id: String,
+    pub owner: Resource,
+    pub renderer: bool,
+    pub renderer_reference: Option<Resource>,
+}
+
+impl EntertainmentConfiguration {
+    pub fn is_active(&self) -> bool {
+        self.status == "active"
+    }
+
+    /// The position of each light in the area. Gradient lights are split into
+    /// several channels, so they're placed at the middle of them.
+    pub fn light_positions(
+        &self,
+        services: &[Entertainment],
+    ) -> Vec<(String, EntertainmentPosition)> {
+        let mut positions: Vec<(String, Vec<EntertainmentPosition>)> = Vec::new();
+
+        for channel in &self.channels {
+            for member in &channel.members {
+                let Some(light) = services
+                    .iter()
+                    .find(|service| service.id == member.service.id)
+                    .and_then(|service| service.renderer_reference.as_ref())
+                else {
+                    continue;
+                };
+
+                match positions.iter_mut().find(|(id, _)| *id == light.id) {
+                    Some((_, light_positions)) => light_positions.push(channel.position),
+                    None => positions.push((light.id.clone(), vec![channel.position])),
+                }
+            }
+        }
+
+        positions
+            .into_iter()
+            .map(|(id, light_positions)| {
+                let count = light_positions.len() as f32;
+                let sum = light_positions
+                    .iter()
+                    .fold((0.0, 0.0, 0.0), |sum, position| {
+                        (sum.0 + position.x, sum.1 + position.y, sum.2 + position.z)
+                    });
+
+                (
+                    id,
+                    EntertainmentPosition {
+                        x: sum.0 / count,
+                        y: sum.1 / count,
+                        z: sum.2 / count,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENTS: &str = include_str!("../../tests/fixtures/hue/events.json");
+    const PLUG: &str = include_str!("../../tests/fixtures/hue/plug.json");
+    const ENTERTAINMENT_CONFIGURATIONS: &str =
+        include_str!("../../tests/fixtures/hue/entertainment_configuration.json");
+    const ENTERTAINMENT: &str = include_str!("../../tests/fixtures/hue/entertainment.json");
+    const GROUPED_LIGHTS: &str = include_str!("../../tests/fixtures/hue/grouped_lights.json");
+
+    fn recorded_events() -> Vec<Event> {
+        serde_json::from_str(EVENTS).unwrap()
+    }
+
+    #[test]
+    fn decodes_each_followed_resource_from_recorded_events() {
+        let events = recorded_events();
+        let messages = &events[0].data;
+
+        let types: Vec<&str> = messages.iter().map(EventMessage::resource_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                "light",
+                "grouped_light",
+                "scene",
+                "room",
+                "zone",
+                "device",
+                "entertainment_configuration"
+            ]
+        );
+        assert_eq!(events[0].kind, EventKind::Update);
+
+        let ResourceDelta::Light(light) = &messages[0].delta else {
+            panic!("expected a light, got {:?}", messages[0].delta);
+        };
+        assert_eq!(light.dimming.as_ref().unwrap().brightness, 64.43);
+        assert_eq!(light.color.as_ref().unwrap().xy.x, 0.4573);
+        assert!(!light.color_temperature.as_ref().unwrap().mirek_valid);
+        let dynamics = light.dynamics.as_ref().unwrap();
+        assert_eq!(dynamics.status.as_deref(), Some("dynamic_palette"));
+        assert_eq!(dynamics.speed_valid, None);
+        assert_eq!(messages[0].owner.as_ref().unwrap().resource_type, "device");
+
+        let ResourceDelta::GroupedLight(group) = &messages[1].delta else {
+            panic!("expected a grouped light, got {:?}", messages[1].delta);
+        };
+        assert!(group.on.as_ref().unwrap().on);
+
+        let ResourceDelta::Scene(scene) = &messages[2].delta else {
+            panic!("expected a scene, got {:?}", messages[2].delta);
+        };
+        assert_eq!(scene.status.as_ref().unwrap().active, "dynamic_palette");
+        assert!(scene.actions.is_none() && scene.palette.is_none());
+
+        let ResourceDelta::Room(room) = &messages[3].delta else {
+            panic!("expected a room, got {:?}", messages[3].delta);
+        };
+        assert_eq!(
+            room.metadata.as_ref().unwrap().name.as_deref(),
+            Some("Living room")
+        );
+
+        let ResourceDelta::Zone(zone) = &messages[4].delta else {
+            panic!("expected a zone, got {:?}", messages[4].delta);
+        };
+        assert_eq!(zone.children.as_ref().unwrap()[0].resource_type, "light");
+
+        let ResourceDelta::Device(device) = &messages[5].delta else {
+            panic!("expected a device, got {:?}", messages[5].delta);
+        };
+        assert!(device.services.is_none());
+
+        let ResourceDelta::EntertainmentConfiguration(entertainment) = &messages[6].delta else {
+            panic!(
+                "expected an entertainment configuration, got {:?}",
+                messages[6].delta
+            );
+        };
+        assert_eq!(entertainment.status.as_deref(), Some("active"));
+        assert_eq!(
+            entertainment
+                .active_streamer
+                .as_ref()
+                .unwrap()
+                .resource_type,
+            "auth_v1"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_other_for_resources_that_arent_followed() {
+        let events = recorded_events();
+        let messages = &events[1].data;
+
+        assert_eq!(messages.len(), 2);
+        for message in messages {
+            assert!(matches!(message.delta, ResourceDelta::Other));
+            assert_eq!(message.resource_type(), "other");
+        }
+        assert_eq!(messages[0].id, "9d1b3f5a-7c2e-4b8d-a0f6-1c3e5a7b9d42");
+    }
+
+    #[test]
+    fn reads_lights_that_cant_be_dimmed() {
+        let response: HueResponse<Light> = serde_json::from_str(PLUG).unwrap();
+        let plug = &response.data[0];
+
+        assert!(plug.dimming.is_none());
+        assert!(plug.color.is_none() && plug.color_temperature.is_none());
+        assert_eq!(plug.metadata.archetype.as_deref(), Some("plug"));
+        assert_eq!(plug.brightness(), 100.0);
+    }
+
+    #[test]
+    fn reads_groups_that_cant_be_dimmed() {
+        let response: HueResponse<GroupedLight> = serde_json::from_str(GROUPED_LIGHTS).unwrap();
+        let groups = &response.data;
+
+        assert_eq!(groups[0].brightness(), 64.43);
+        assert!(groups[1].dimming.is_none());
+        assert!(!groups[1].on.on);
+        assert_eq!(groups[1].brightness(), 100.0);
+    }
+
+    #[test]
+    fn places_lights_in_an_entertainment_area() {
+        let areas: HueResponse<EntertainmentConfiguration> =
+            serde_json::from_str(ENTERTAINMENT_CONFIGURATIONS).unwrap();
+        let services: HueResponse<Entertainment> = serde_json::from_str(ENTERTAINMENT).unwrap();
+        let (tv, music) = (&areas.data[0], &areas.data[1]);
+
+        assert!(tv.is_active());
+        assert!(tv.active_streamer.is_some());
+        assert!(!music.is_active());
+
+        // The desk light renders two channels, so it sits between them. The
+        // last channel's service isn't one the bridge lists, so it's skipped.
+        let positions = tv.light_positions(&services.data);
+        let positions: Vec<(&str, [f32; 3])> = positions
+            .iter()
+            .map(|(id, position)| (id.as_str(), [position.x, position.y, position.z]))
+            .collect();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(
+            positions[0],
+            ("3a6710fa-4474-4eba-b533-5e6e72968feb", [-0.8, 0.8, -0.2])
+        );
+        assert_eq!(positions[1].0, "8f0d1c5e-51a9-4c1e-a3f2-2f61d2b0c6a4");
+        for (actual, expected) in positions[1].1.iter().zip([0.4, 0.8, 0.6]) {
+            assert!((actual - expected).abs() < 1e-6, "{:?}", positions[1]);
+        }
+
+        assert!(tv.light_positions(&[]).is_empty());
+    }
+}
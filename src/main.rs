@@ -2,146 +2,201 @@
 extern crate log;
 extern crate simplelog;
 
-use std::{collections::HashSet, str::FromStr, thread, time};
-
-use color::{HSVColor, Palette};
-use hue::client::Hue;
-use log::LevelFilter;
-use nanoleaf::{
-    client::Nanoleaf,
-    types::{Effect, Range},
+use std::time::Duration;
+
+use clap::Parser;
+use cli::{Cli, Command, HueCommand, NanoleafCommand};
+use futures_util::{
+    future::{join_all, try_join_all},
+    stream::select_all,
+    StreamExt,
 };
-use serde::Deserialize;
 use simplelog::{ColorChoice, CombinedLogger, Config, TermLogger, TerminalMode};
+use tokio::time::MissedTickBehavior;
 
 use crate::{
-    color::RGBColor,
-    hue::types::{EventMessage, Light},
-    room::Room,
+    bridge::Bridge,
+    config::read_config,
+    hue::{cache::ResourceCache, types::EventKind},
+    mirror::{Mirror, SAMPLE_INTERVAL},
+    nanoleaf::types::EventType,
+    supervisor::{Backoff, EventSupervisor, NanoleafSupervisor, Supervised},
 };
 
+mod bridge;
+mod cli;
 mod color;
+mod commands;
+mod config;
+mod discovery;
 mod hue;
+mod mirror;
 mod nanoleaf;
 mod room;
-
-#[derive(Debug, Deserialize)]
-struct HueConfig {
-    host: String,
-    group: String,
-    username: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct NanoleafConfig {
-    host: String,
-    token: String,
-    max_brightness: u8,
-}
-
-#[derive(Debug, Deserialize)]
-struct NanoHueConfig {
-    hue: HueConfig,
-    nanoleaf: NanoleafConfig,
-}
-
-fn read_config(path: &str) -> Result<NanoHueConfig, Box<dyn std::error::Error>> {
-    let f = std::fs::File::open(path)?;
-    let config: NanoHueConfig = serde_yaml::from_reader::<std::fs::File, NanoHueConfig>(f)?;
-
-    Ok(config)
-}
-
-async fn get_palette(lights: &Vec<Light>) -> Palette {
-    println!("{:?}", lights);
-
-    let mut palette = Palette::new();
-
-    for light in lights {
-        let color = light.color.unwrap();
-        let color_coordinates = color.xy;
-        let color_gamut = color.gamut.unwrap();
-        let brightness = light.dimming.brightness;
-
-        let color = RGBColor::from_coordinate(color_coordinates, color_gamut, brightness);
-        palette.insert(color.to_hsv());
+mod spatial;
+mod sse;
+mod supervisor;
+#[cfg(test)]
+mod testing;
+
+/// Follow a single bridge's event stream, fanning each change out to the
+/// mirrors whose sources live on it.
+async fn run_bridge(bridge: Bridge) -> Result<(), Box<dyn std::error::Error>> {
+    let hue_client = bridge.hue;
+    let mut cache = bridge.cache;
+    let mut mirrors = bridge.mirrors;
+
+    for mirror in mirrors.iter_mut() {
+        mirror.sync(&cache)?;
+        mirror.flush().await;
     }
 
-    palette
-}
+    let events = EventSupervisor::new(&hue_client, Backoff::default())
+        .with_bridge_id(bridge.bridge_id)
+        .into_stream();
+    tokio::pin!(events);
+    let mut needs_resync = false;
+
+    // Changes made on the Nanoleaf. Power and brightness are only needed by
+    // mappings that send them back.
+    let mut nanoleaf_events = select_all(mirrors.iter().enumerate().flat_map(|(index, mirror)| {
+        let types: &[EventType] = if mirror.follows_nanoleaf() {
+            &[EventType::State, EventType::Layout, EventType::Effects]
+        } else {
+            &[EventType::Layout, EventType::Effects]
+        };
+
+        mirror
+            .outputs()
+            .iter()
+            .enumerate()
+            .map(move |(output_index, output)| {
+                NanoleafSupervisor::new(
+                    &output.name,
+                    output.nanoleaf.clone(),
+                    types,
+                    Backoff::default(),
+                )
+                .with_device_id(output.device_id.clone())
+                .into_stream()
+                .map(move |event| (index, output_index, event))
+                .boxed()
+            })
+    }));
+
+    let mut sampling = tokio::time::interval(SAMPLE_INTERVAL);
+    sampling.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
-async fn write_room_to_nanoleaf(nanoleaf_client: &Nanoleaf, room: &Room) {
-    // Write the room state to the nanoleaf
-    if !room.has_updated && !room.scene_has_updated {
-        return;
-    }
+    loop {
+        let is_sampling = mirrors.iter().any(Mirror::is_sampling);
+
+        tokio::select! {
+            Some(event) = events.next() => match event {
+                Ok(Supervised::Reconnected) => needs_resync = true,
+                Ok(Supervised::Event(event)) => {
+                    for item in event.data {
+                        trace!(target: "nanohue", "{:?} {:?}", event.kind, item);
+                        cache.apply(&hue_client, event.kind, &item).await;
+
+                        match event.kind {
+                            EventKind::Update => {
+                                for mirror in mirrors.iter_mut() {
+                                    mirror.process_message(&cache, &item);
+                                }
+                            }
+                            EventKind::Error => warn!(
+                                target: "nanohue",
+                                "The bridge reported an error with {} {}.",
+                                item.resource_type(),
+                                item.id
+                            ),
+                            EventKind::Add | EventKind::Delete => {}
+                        }
+                    }
+                }
+                Err(err) => {
+                    return Err(
+                        format!("lost the connection to bridge '{}': {}", bridge.name, err).into(),
+                    )
+                }
+            },
+            Some((index, output, event)) = nanoleaf_events.next(), if !nanoleaf_events.is_empty() => {
+                trace!(target: "nanohue", "{:?}", event);
+                mirrors[index]
+                    .process_nanoleaf_event(&hue_client, output, &event)
+                    .await;
+            }
+            // The lights are fetched once for every mirror that needs them.
+            _ = sampling.tick(), if is_sampling => match hue_client.lights().await {
+                Ok(lights) => {
+                    cache.set_lights(lights);
+                    for mirror in mirrors.iter_mut().filter(|mirror| mirror.is_sampling()) {
+                        mirror.sample(cache.lights());
+                    }
+                }
+                Err(err) => warn!(target: "nanohue", "Unable to sample lights. {}", err),
+            },
+        }
 
-    // On State
-    let _ = nanoleaf_client.set_power(room.on).await;
+        // Anything could have happened while we were disconnected, so rebuild
+        // the mirrors from the bridge's current state. If the bridge isn't ready
+        // yet, try again after the next event.
+        if needs_resync {
+            match ResourceCache::load(&hue_client).await {
+                Ok(resources) => {
+                    cache = resources;
+                    needs_resync = false;
+                    for mirror in mirrors.iter_mut() {
+                        if let Err(err) = mirror.sync(&cache) {
+                            warn!(target: "nanohue", "Unable to resynchronize '{}'. {}", mirror.name(), err);
+                        }
+                    }
+                }
+                Err(err) => {
+                    warn!(target: "nanohue", "Unable to reload the bridge's resources. {}", err)
+                }
+            }
+        }
 
-    if !room.on {
-        return;
+        join_all(mirrors.iter_mut().map(|mirror| mirror.flush())).await;
     }
+}
 
-    // Brightness
-    let _ = nanoleaf_client
-        .set_brightness(room.get_brightness(), 1)
-        .await;
-
-    let palette = room.palette.clone();
-
-    // Effect
+async fn run(config_path: &str, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let config = read_config(config_path)?;
 
-    if room.scene_has_updated {
-        match palette {
-            Some(palette) => {
-                let animation_type = if room.dynamic {
-                    String::from_str("random").unwrap()
-                } else {
-                    String::from_str("flow").unwrap()
-                };
+    let connection = bridge::connect(&config, dry_run).await?;
 
-                let effect = Effect {
-                    command: String::from_str("display").unwrap(),
-                    animation_name: String::from_str("hue").unwrap(),
-                    color_type: String::from_str("HSB").unwrap(),
-                    animation_data: None,
-                    brightness_range: Range {
-                        min: 25,
-                        max: room.brightness.clamp(0.0, 100.0) as u32,
-                    },
-                    loop_animation: true,
-                    animation_type: animation_type,
-                    transition_time: if room.dynamic {
-                        Range { min: 15, max: 30 }
-                    } else {
-                        Range { min: 30, max: 60 }
-                    },
-                    delay_time: if room.dynamic {
-                        Range { min: 30, max: 60 }
-                    } else {
-                        Range { min: 60, max: 90 }
-                    },
-                    palette: palette.iter().cloned().collect::<Vec<HSVColor>>(),
-                };
+    for bridge in &connection.bridges {
+        for mirror in &bridge.mirrors {
+            info!(target: "nanohue", "{}", bridge::describe_mirror(bridge, mirror));
 
-                let _ = nanoleaf_client.write_effect(effect.clone()).await;
-            }
-            None => {
-                let color_temp = 1000000_u32 / room.color_temperature.unwrap();
-                let _ = nanoleaf_client.set_color_temperature(color_temp).await;
+            if mirror.follows_entertainment() {
+                warn!(
+                    target: "nanohue",
+                    "The {} '{}' can't follow its entertainment area's stream, which is encrypted. While the area is streaming, its lights are sampled from the bridge every {:?} instead, and may lag behind.",
+                    mirror.kind(),
+                    mirror.name(),
+                    SAMPLE_INTERVAL
+                );
             }
         }
+    }
 
-        // let _ = nanoleaf_client.set_effect(effect.animation_name).await;
-    };
+    // Each bridge has its own event stream. They're followed side by side, and
+    // only stop if one of them fails in a way that can't be retried.
+    try_join_all(connection.bridges.into_iter().map(run_bridge)).await?;
+
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+
     CombinedLogger::init(vec![
         TermLogger::new(
-            LevelFilter::Trace,
+            cli.log_level,
             Config::default(),
             TerminalMode::Mixed,
             ColorChoice::Auto,
@@ -150,171 +205,102 @@ async fn main() {
     ])
     .unwrap();
 
-    let config = read_config("config.yml").unwrap();
-
-    let hue_client = Hue::new(config.hue.host, config.hue.username).unwrap();
-
-    let nanoleaf = Nanoleaf::new(config.nanoleaf.host, config.nanoleaf.token).unwrap();
-
-    let rooms = hue_client.rooms().await.unwrap();
-
-    let hue_room: &crate::hue::types::Room = rooms
-        .iter()
-        .filter(|&r| r.metadata.name == config.hue.group)
-        .nth(0)
-        .unwrap();
-    trace!(
-        target: "nanohue",
-        "Found the room defined in the configuration. {:?}",
-        hue_room
-    );
-
-    let room_devices: HashSet<&str> = hue_room
-        .children
-        .iter()
-        .filter(|&child| child.resource_type == "device")
-        .map(|device| device.id.as_str())
-        .collect();
-
-    let group_resource = hue_room
-        .services
-        .iter()
-        .filter(|&r| r.resource_type == "grouped_light")
-        .nth(0)
-        .unwrap();
-
-    trace!(
-            target: "nanohue",
-            "Found the room's group resource. {:?}",
-            group_resource
-    );
-
-    let group = hue_client.group(&group_resource.id).await.unwrap();
-    trace!(
-            target: "nanohue",
-            "Found the room's grouped light. {:?}", group
-    );
-
-    let all_lights = hue_client.lights().await.unwrap();
-    let lights = all_lights
-        .into_iter()
-        .filter(|light| room_devices.contains(light.owner.id.as_str()))
-        .collect();
-
-    let mut room = Room {
-        on: group.on.on,
-        brightness: group.dimming.brightness,
-        dynamic: false,
-        palette: Some(get_palette(&lights).await),
-        has_updated: true,
-        scene_has_updated: true,
-        color_temperature: None,
-        max_brightness: config.nanoleaf.max_brightness,
-    };
-
-    trace!(target: "nanohue", "Generated a baseline room. {:?}", room);
-    write_room_to_nanoleaf(&nanoleaf, &mut room).await;
-
-    loop {
-        trace!(target: "nanohue", "Looping");
-
-        let event = hue_client.get_event_stream().await.unwrap();
-
-        let event_data: Vec<EventMessage> =
-            event.into_iter().map(|item| item.data).flatten().collect();
-
-        let allowed_types = vec!["grouped_light", "light", "scene"];
-
-        for item in event_data {
-            let message_type = item.message_type.as_str();
-
-            if !allowed_types.contains(&message_type) {
-                continue;
-            }
-
-            if message_type == "grouped_light" {
-                // It's a grouped light! confirm that it's the right ID.
-                if item.id != group.id {
-                    trace!(target: "nanohue", "Message pertains to a different group. Skipping.");
-                    continue;
+    let result = match cli.command {
+        Command::Run { dry_run } => run(&cli.config, dry_run).await,
+        Command::CheckConfig => commands::check::check_config(&cli.config).await,
+        Command::PairHue {
+            host,
+            name,
+            timeout,
+        } => {
+            commands::pair::pair_hue(
+                &host,
+                name.as_deref(),
+                &cli.config,
+                Duration::from_secs(timeout),
+            )
+            .await
+        }
+        Command::PairNanoleaf {
+            host,
+            name,
+            timeout,
+        } => {
+            commands::pair::pair_nanoleaf(
+                &host,
+                name.as_deref(),
+                &cli.config,
+                Duration::from_secs(timeout),
+            )
+            .await
+        }
+        Command::Discover { timeout, format } => {
+            commands::discover::discover_devices(Duration::from_secs(timeout), format).await
+        }
+        Command::Hue {
+            format,
+            bridge,
+            command,
+        } => {
+            let bridge = bridge.as_deref();
+            match command {
+                HueCommand::Rooms => commands::hue::rooms(&cli.config, bridge, format).await,
+                HueCommand::Zones => commands::hue::zones(&cli.config, bridge, format).await,
+                HueCommand::Lights => commands::hue::lights(&cli.config, bridge, format).await,
+                HueCommand::Entertainment => {
+                    commands::hue::entertainment(&cli.config, bridge, format).await
                 }
-                trace!(target: "nanohue", "Message pertains to the group. {:?}.", item);
-
-                // Check if the on status has changed, and if so, write it to the room.
-                match &item.on {
-                    Some(on) => {
-                        room.on = on.on;
-                        room.has_updated = true
-                    }
-                    None => {}
+                HueCommand::Scenes { room } => {
+                    commands::hue::scenes(&cli.config, bridge, room.as_deref(), format).await
                 }
-
-                // Check if the brightness has changed, and if so, write it to the room.
-                match &item.dimming {
-                    Some(dimming) => {
-                        room.brightness = dimming.brightness;
-                        room.has_updated = true
-                    }
-                    None => {}
+                HueCommand::Recall {
+                    scene,
+                    room,
+                    dynamic,
+                    brightness,
+                } => {
+                    commands::hue::recall(
+                        &cli.config,
+                        bridge,
+                        &scene,
+                        room.as_deref(),
+                        dynamic,
+                        brightness,
+                    )
+                    .await
                 }
-            } else if message_type == "scene" {
-                // Check the scene change! If it is part of our room, grab the new palette
-
-                let scene = hue_client.scene(&item.id).await.unwrap();
-
-                if scene.group.id != hue_room.id || scene.status.active == "inactive" {
-                    continue;
+            }
+        }
+        Command::Nanoleaf {
+            format,
+            device,
+            command,
+        } => {
+            let device = device.as_deref();
+            match command {
+                NanoleafCommand::Info => {
+                    commands::nanoleaf::info(&cli.config, device, format).await
                 }
-
-                // Construct a color palette from the scene. For this, we have to iterate through
-                // the actions to get the light's color gamut, the color, and the brightness. Then,
-                // we can compute an HSVColor and store that in the room's palette.
-
-                let mut palette = Palette::new();
-
-                for action in scene.actions {
-                    let light = hue_client.light(&action.target.id).await.unwrap();
-
-                    match action.action.color {
-                        Some(color) => {
-                            let color = RGBColor::from_coordinate(
-                                color.xy,
-                                light.color.unwrap().gamut.unwrap(),
-                                action.action.dimming.brightness,
-                            );
-                            palette.insert(color.to_hsv());
-                        }
-                        None => match action.action.color_temperature {
-                            Some(color_temperature) => {
-                                room.color_temperature = Some(color_temperature.mirek);
-                            }
-                            None => {}
-                        },
-                    }
+                NanoleafCommand::Layout => {
+                    commands::nanoleaf::layout(&cli.config, device, format).await
                 }
-
-                room.dynamic = scene.status.active == "dynamic_palette";
-                if palette.len() > 0 {
-                    room.palette = Some(palette);
-                } else {
-                    room.palette = None;
+                NanoleafCommand::Effects => {
+                    commands::nanoleaf::effects(&cli.config, device, format).await
                 }
-
-                room.scene_has_updated = true;
-                room.has_updated = true;
+                NanoleafCommand::State => {
+                    commands::nanoleaf::state(&cli.config, device, format).await
+                }
+                NanoleafCommand::Identify { duration } => {
+                    commands::nanoleaf::identify(&cli.config, device, Duration::from_secs(duration))
+                        .await
+                }
+                NanoleafCommand::Events => commands::nanoleaf::events(&cli.config, device).await,
             }
-
-            println!("{:?}", item);
-        }
-
-        if room.has_updated {
-            write_room_to_nanoleaf(&nanoleaf, &room).await;
-            room.has_updated = false;
-            room.scene_has_updated = false;
         }
+    };
 
-        // Pause briefly to prevent overloading the nanoleaf.
-        let ten_millis = time::Duration::from_millis(10);
-        thread::sleep(ten_millis);
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
     }
 }
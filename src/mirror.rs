@@ -0,0 +1,1602 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures_util::future::join_all;
+
+use crate::{
+    color::{mirek_to_kelvin, ColorGamut2, Palette, RGBColor, FULL_BRIGHTNESS},
+    config::{EffectConfig, PaletteConfig, SourceKind},
+    discovery::{self, DeviceKind},
+    hue::{
+        cache::ResourceCache,
+        client::Hue,
+        error::HueError,
+        types::{
+            self, Color, ColorUpdate, Dimming, EntertainmentConfigurationDelta, EventMessage,
+            Gradient, Light, LightColorTemperature, LightDelta, LightUpdate, OnStatus,
+            ResourceDelta, Scene,
+        },
+    },
+    nanoleaf::{
+        client::{Nanoleaf, COLOR_TEMPERATURE_RANGE},
+        error::NanoleafError,
+        stream::ExternalControl,
+        types::{
+            Effect, LayoutChange, NanoleafEvent, PaletteColor, PanelLayout, Range, StateChange,
+        },
+    },
+    room::Room,
+    spatial::{LightColor, PanelMap, SpatialLayout},
+};
+
+/// A Nanoleaf controller that a mapping writes to.
+#[derive(Clone)]
+pub struct Output {
+    /// The name of the device in the configuration.
+    pub name: String,
+    /// The name the controller reports for itself.
+    pub panel_name: String,
+    pub nanoleaf: Arc<Nanoleaf>,
+    pub device_id: Option<String>,
+    pub layout: PanelLayout,
+    /// Set while the controller is being looked up again, so that failures in
+    /// the meantime don't start more lookups.
+    pub resolving: Arc<AtomicBool>,
+}
+
+/// The Hue resource a mirror takes its colors and brightness from.
+pub enum Source {
+    /// A room or zone, followed through its grouped light and active scene.
+    Group {
+        kind: SourceKind,
+        group: types::Room,
+        grouped_light: String,
+    },
+    /// A single light, followed through its own state.
+    Light(Box<Light>),
+}
+
+/// Mirrors a single Hue room, zone or light to one or more Nanoleaf
+/// controllers. Events from the bridge are fed in through `process_message`,
+/// and the resulting state is written out with `flush`.
+pub struct Mirror {
+    source: Source,
+    outputs: Vec<Output>,
+    effect: EffectConfig,
+    spatial: Option<Spatial>,
+    /// The gamut of each light in a room or zone, for converting scene colors.
+    gamuts: HashMap<String, ColorGamut2>,
+    reverse: Option<ReverseSync>,
+    /// One for each output. When it started showing an effect someone picked
+    /// on it, which it is left to show until the next scene.
+    paused: Vec<Option<Instant>>,
+    room: Room,
+}
+
+/// Per-panel colors streamed to every output, for mappings with a layout.
+struct Spatial {
+    layout: SpatialLayout,
+    /// One for each output.
+    maps: Vec<PanelMap>,
+    streams: Vec<Option<ExternalControl>>,
+    /// One for each light in the layout.
+    colors: Vec<Option<LightColor>>,
+    gamuts: Vec<Option<ColorGamut2>>,
+    /// Whether something is streaming to the layout's entertainment area.
+    streaming: bool,
+    has_updated: bool,
+}
+
+/// How often lights are sampled while their entertainment area is streaming.
+/// The stream itself is encrypted between the streamer and the bridge, so the
+/// lights' reported state is the closest nanohue can get to it. The bridge
+/// doesn't reliably keep that state in step with the stream, so lights may
+/// report stale colors for a while. Sampling is best effort, and infrequent
+/// so that it doesn't hold up commands, which share the same rate limit.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The name of the effects written to the Nanoleaf.
+const EFFECT_NAME: &str = "hue";
+
+/// How long a change may take to be reported back after it was written.
+const ECHO_WINDOW: Duration = Duration::from_secs(3);
+
+/// The power and brightness values recently written to one side of a mirror,
+/// so that reports of them can be told apart from changes someone else made.
+#[derive(Default)]
+struct Echoes {
+    power: Vec<(Instant, bool)>,
+    brightness: Vec<(Instant, u32)>,
+}
+
+impl Echoes {
+    fn record(&mut self, on: Option<bool>, brightness: Option<u32>) {
+        let now = Instant::now();
+        self.prune(now);
+
+        if let Some(on) = on {
+            self.power.push((now, on));
+        }
+        if let Some(brightness) = brightness {
+            self.brightness.push((now, brightness));
+        }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        self.power.retain(|(at, _)| now - *at < ECHO_WINDOW);
+        self.brightness.retain(|(at, _)| now - *at < ECHO_WINDOW);
+    }
+
+    fn is_power_echo(&mut self, on: bool) -> bool {
+        self.prune(Instant::now());
+        self.power.iter().any(|(_, value)| *value == on)
+    }
+
+    fn is_brightness_echo(&mut self, brightness: u32) -> bool {
+        self.prune(Instant::now());
+        self.brightness
+            .iter()
+            .any(|(_, value)| *value == brightness)
+    }
+}
+
+/// Whether an effect is one saved on the controller, rather than one written
+/// by nanohue or one without a name of its own, such as a solid color or the
+/// colors being streamed to it.
+fn is_saved_effect(name: &str) -> bool {
+    !name.starts_with('*') && name != EFFECT_NAME
+}
+
+/// For mappings that also send changes made on the Nanoleaf back to Hue.
+#[derive(Default)]
+struct ReverseSync {
+    /// Written to the Nanoleaf.
+    written: Echoes,
+    /// Sent to the bridge.
+    sent: Echoes,
+    /// The output whose power or brightness change is waiting to be written
+    /// to the others. It already shows the change, and writing the room back
+    /// would clamp it to `max_brightness`.
+    origin: Option<usize>,
+}
+
+/// Combine each light's color and brightness into a palette, merging colors
+/// that look alike.
+fn build_palette(colors: &[(RGBColor, f32)], settings: &PaletteConfig) -> Palette {
+    let palette = Palette::new(colors, settings.merge_threshold, settings.max_colors);
+    trace!(target: "nanohue", "Reduced {} colors to {:?}", colors.len(), palette);
+
+    palette
+}
+
+/// Each color along a gradient, sharing the brightness between them.
+fn gradient_colors(
+    gradient: Option<&Gradient>,
+    gamut: ColorGamut2,
+    brightness: f32,
+) -> Option<Vec<(RGBColor, f32)>> {
+    let points = &gradient?.points;
+    if points.is_empty() {
+        return None;
+    }
+
+    let share = brightness / points.len() as f32;
+    Some(
+        points
+            .iter()
+            .map(|point| {
+                (
+                    RGBColor::from_coordinate(point.color.xy, gamut, FULL_BRIGHTNESS),
+                    share,
+                )
+            })
+            .collect(),
+    )
+}
+
+fn get_palette(lights: &[Light], settings: &PaletteConfig) -> Palette {
+    trace!(target: "nanohue", "Building a palette from {:?}", lights);
+
+    let mut colors = Vec::new();
+
+    for light in lights {
+        let mirek = light
+            .color_temperature
+            .as_ref()
+            .filter(|color_temperature| color_temperature.mirek_valid)
+            .and_then(|color_temperature| color_temperature.mirek);
+        let xy = light
+            .color
+            .as_ref()
+            .and_then(|color| Some((color.xy, color.gamut?)));
+
+        // Brighter lights cover more of the room, so they get more of the
+        // palette rather than a brighter color. The Nanoleaf's own brightness
+        // follows the room.
+        let brightness = light.brightness();
+        match (mirek, xy) {
+            (Some(mirek), _) => {
+                colors.push((RGBColor::from_kelvin(mirek_to_kelvin(mirek)), brightness))
+            }
+            (None, Some((color_coordinates, color_gamut))) => {
+                match gradient_colors(light.gradient.as_ref(), color_gamut, brightness) {
+                    Some(points) => colors.extend(points),
+                    None => colors.push((
+                        RGBColor::from_coordinate(color_coordinates, color_gamut, FULL_BRIGHTNESS),
+                        brightness,
+                    )),
+                }
+            }
+            (None, None) => continue,
+        };
+    }
+
+    build_palette(&colors, settings)
+}
+
+/// How likely a color in a Nanoleaf effect's palette is to be shown. Colors
+/// without a probability are treated as the least likely.
+fn probability(color: &PaletteColor) -> f32 {
+    color.probability.unwrap_or(0.0)
+}
+
+/// The gamut of each color light, so that scene colors can be converted
+/// without fetching every light in the scene.
+fn light_gamuts(lights: &[Light]) -> HashMap<String, ColorGamut2> {
+    lights
+        .iter()
+        .filter_map(|light| Some((light.id.clone(), light.color.as_ref()?.gamut?)))
+        .collect()
+}
+
+fn log_nanoleaf_error(name: &str, err: &NanoleafError) {
+    if err.is_retryable() {
+        warn!(target: "nanohue", "Failed to update the Nanoleaf '{}', will retry on the next change. {}", name, err);
+    } else {
+        error!(target: "nanohue", "The Nanoleaf '{}' rejected an update. {}", name, err);
+    }
+}
+
+async fn write_room_to_nanoleaf(
+    nanoleaf_client: &Nanoleaf,
+    room: &Room,
+    settings: &EffectConfig,
+    write_effect: bool,
+) -> Result<(), NanoleafError> {
+    // Write the room state to the nanoleaf
+    if !room.has_updated && !room.scene_has_updated {
+        return Ok(());
+    }
+
+    // On State
+    nanoleaf_client.set_power(room.on).await?;
+
+    if !room.on {
+        return Ok(());
+    }
+
+    // Brightness
+    nanoleaf_client
+        .set_brightness(room.get_brightness(), 1)
+        .await?;
+
+    let palette = room.palette.clone();
+
+    // Effect
+
+    if room.scene_has_updated && write_effect {
+        match palette {
+            Some(palette) => {
+                let animation_type = if room.dynamic {
+                    String::from_str("random").unwrap()
+                } else {
+                    String::from_str("flow").unwrap()
+                };
+
+                let (transition_time, delay_time) = if room.dynamic {
+                    (
+                        settings.dynamic_transition_time,
+                        settings.dynamic_delay_time,
+                    )
+                } else {
+                    (settings.transition_time, settings.delay_time)
+                };
+
+                let effect = Effect {
+                    command: String::from_str("display").unwrap(),
+                    animation_name: String::from_str(EFFECT_NAME).unwrap(),
+                    color_type: String::from_str("HSB").unwrap(),
+                    animation_data: None,
+                    brightness_range: Range {
+                        min: settings.min_brightness,
+                        max: room.brightness.clamp(0.0, 100.0) as u32,
+                    },
+                    loop_animation: true,
+                    animation_type,
+                    transition_time: Range {
+                        min: transition_time.min,
+                        max: transition_time.max,
+                    },
+                    delay_time: Range {
+                        min: delay_time.min,
+                        max: delay_time.max,
+                    },
+                    palette: palette
+                        .probabilities()
+                        .into_iter()
+                        .map(|(color, probability)| PaletteColor {
+                            color,
+                            probability: Some(probability),
+                        })
+                        .collect(),
+                };
+
+                nanoleaf_client.write_effect(effect.clone()).await?;
+            }
+            None => {
+                let Some(mirek) = room.color_temperature else {
+                    return Ok(());
+                };
+
+                // Hue's coolest whites are a little cooler than the Nanoleaf's.
+                let kelvin = mirek_to_kelvin(mirek).clamp(
+                    *COLOR_TEMPERATURE_RANGE.start(),
+                    *COLOR_TEMPERATURE_RANGE.end(),
+                );
+                nanoleaf_client.set_color_temperature(kelvin).await?;
+            }
+        }
+    };
+
+    Ok(())
+}
+
+/// Write the room to a Nanoleaf, leaving the effect alone if colors are being
+/// streamed to it instead.
+async fn update_nanoleaf(
+    output: &Output,
+    room: &Room,
+    settings: &EffectConfig,
+    write_effect: bool,
+) {
+    let Err(err) = write_room_to_nanoleaf(&output.nanoleaf, room, settings, write_effect).await
+    else {
+        return;
+    };
+
+    handle_nanoleaf_error(output, &err).await;
+}
+
+/// Log a failed update, and look the controller up again if it has become
+/// unreachable. The lookup browses the network for several seconds, so it runs
+/// in the background rather than holding up updates to the other controllers.
+async fn handle_nanoleaf_error(output: &Output, err: &NanoleafError) {
+    log_nanoleaf_error(&output.name, err);
+
+    let (true, Some(device_id)) = (err.is_retryable(), output.device_id.clone()) else {
+        return;
+    };
+    if output.resolving.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let nanoleaf = Arc::clone(&output.nanoleaf);
+    let resolving = Arc::clone(&output.resolving);
+    tokio::spawn(async move {
+        if let Some(host) = discovery::resolve(DeviceKind::Nanoleaf, &device_id).await {
+            info!(target: "nanohue", "Found Nanoleaf {} at {}.", device_id, host);
+            nanoleaf.set_host(&host);
+        }
+        resolving.store(false, Ordering::SeqCst);
+    });
+}
+
+/// The colors a scene is made from, and the color temperatures of its white
+/// lights, each along with their brightness.
+struct SceneColors {
+    colors: Vec<(RGBColor, f32)>,
+    whites: Vec<(u32, f32)>,
+}
+
+/// Find the colors a scene is made from. The scene's own palette is used
+/// when it has one, since that's what dynamic scenes cycle through. Otherwise
+/// the colors are taken from what the scene sets each light to.
+fn scene_colors(scene: &Scene, gamuts: &HashMap<String, ColorGamut2>) -> SceneColors {
+    let palette = &scene.palette;
+
+    if !palette.color.is_empty() || !palette.color_temperature.is_empty() {
+        // Palette colors aren't tied to a light, so there's no gamut to hand.
+        let gamut = ColorGamut2::default();
+
+        let colors = palette
+            .color
+            .iter()
+            .map(|entry| {
+                (
+                    RGBColor::from_coordinate(entry.color.xy, gamut, FULL_BRIGHTNESS),
+                    entry.dimming.brightness,
+                )
+            })
+            .collect();
+        let whites = palette
+            .color_temperature
+            .iter()
+            .map(|entry| (entry.color_temperature.mirek, entry.dimming.brightness))
+            .collect();
+
+        return SceneColors { colors, whites };
+    }
+
+    let default_brightness = palette
+        .dimming
+        .first()
+        .map_or(100_f32, |dimming| dimming.brightness);
+
+    let mut colors = Vec::new();
+    let mut whites = Vec::new();
+
+    for action in &scene.actions {
+        let action_brightness = action
+            .action
+            .dimming
+            .as_ref()
+            .map_or(default_brightness, |dimming| dimming.brightness);
+        let gamut = gamuts.get(&action.target.id).copied().unwrap_or_default();
+
+        if let Some(points) =
+            gradient_colors(action.action.gradient.as_ref(), gamut, action_brightness)
+        {
+            colors.extend(points);
+            continue;
+        }
+
+        match (&action.action.color, &action.action.color_temperature) {
+            (Some(color), _) => colors.push((
+                RGBColor::from_coordinate(color.xy, gamut, FULL_BRIGHTNESS),
+                action_brightness,
+            )),
+            (None, Some(color_temperature)) => {
+                whites.push((color_temperature.mirek, action_brightness))
+            }
+            (None, None) => {}
+        }
+    }
+
+    SceneColors { colors, whites }
+}
+
+fn apply_scene(
+    scene: &Scene,
+    room: &mut Room,
+    settings: &PaletteConfig,
+    gamuts: &HashMap<String, ColorGamut2>,
+) {
+    // A scene that's entirely white is shown as a color temperature rather than
+    // an effect.
+    let SceneColors { mut colors, whites } = scene_colors(scene, gamuts);
+
+    room.dynamic = scene.status.active == "dynamic_palette";
+    room.color_temperature = None;
+    room.palette = None;
+
+    if colors.is_empty() {
+        // Average the whites, giving brighter lights more say.
+        let total: f32 = whites.iter().map(|(_, brightness)| brightness).sum();
+        room.color_temperature = match whites.as_slice() {
+            [] => None,
+            _ if total <= 0.0 => Some(whites[0].0),
+            _ => Some(
+                (whites
+                    .iter()
+                    .map(|(mirek, brightness)| *mirek as f32 * brightness)
+                    .sum::<f32>()
+                    / total)
+                    .round() as u32,
+            ),
+        };
+    } else {
+        colors.extend(whites.into_iter().map(|(mirek, brightness)| {
+            (RGBColor::from_kelvin(mirek_to_kelvin(mirek)), brightness)
+        }));
+        room.palette = Some(build_palette(&colors, settings));
+    }
+
+    room.scene_has_updated = true;
+    room.has_updated = true;
+}
+
+/// Build a room or zone's state from its grouped light, its lights, and its
+/// active scene. Rooms list the devices that own their lights, while zones
+/// usually list the lights themselves.
+fn sync_group(
+    cache: &ResourceCache,
+    group: &types::Room,
+    grouped_light: &str,
+    max_brightness: u8,
+    settings: &PaletteConfig,
+) -> Result<(Room, Vec<Light>), HueError> {
+    let group_light = cache
+        .grouped_light(grouped_light)
+        .ok_or_else(|| HueError::NotFound {
+            resource: "grouped light",
+            id: grouped_light.to_string(),
+        })?;
+    trace!(
+            target: "nanohue",
+            "Found the grouped light for '{}'. {:?}", group.metadata.name, group_light
+    );
+
+    let lights: Vec<Light> = cache
+        .lights()
+        .iter()
+        .filter(|light| group.contains_light(light))
+        .cloned()
+        .collect();
+
+    let mut room = Room {
+        on: group_light.on.on,
+        brightness: group_light.brightness(),
+        dynamic: false,
+        palette: Some(get_palette(&lights, settings)),
+        has_updated: true,
+        scene_has_updated: true,
+        color_temperature: None,
+        max_brightness,
+    };
+
+    let active_scene = cache
+        .scenes()
+        .iter()
+        .find(|scene| scene.group.id == group.id && scene.status.active != "inactive");
+
+    if let Some(scene) = active_scene {
+        trace!(target: "nanohue", "Found the active scene for '{}'. {:?}", group.metadata.name, scene.id);
+        apply_scene(scene, &mut room, settings, &light_gamuts(&lights));
+    }
+
+    Ok((room, lights))
+}
+
+/// Point the room at a single light's current color, or its color temperature
+/// when it isn't in color mode. The light's brightness is mirrored separately,
+/// so the color itself is kept at full brightness.
+fn apply_light_color(
+    room: &mut Room,
+    gamut: Option<ColorGamut2>,
+    color: Option<&Color>,
+    color_temperature: Option<&LightColorTemperature>,
+) {
+    if let Some(mirek) = color_temperature
+        .filter(|color_temperature| color_temperature.mirek_valid)
+        .and_then(|color_temperature| color_temperature.mirek)
+    {
+        room.palette = None;
+        room.color_temperature = Some(mirek);
+    } else if let (Some(color), Some(gamut)) = (color, gamut) {
+        let color = RGBColor::from_coordinate(color.xy, gamut, FULL_BRIGHTNESS);
+        room.palette = Some(Palette::single(color.to_hsv()));
+    } else {
+        return;
+    }
+
+    room.scene_has_updated = true;
+    room.has_updated = true;
+}
+
+/// A light's color at full brightness, from its color temperature when it's in
+/// white mode, or its color otherwise.
+fn light_rgb(
+    gamut: Option<ColorGamut2>,
+    color: Option<&Color>,
+    color_temperature: Option<&LightColorTemperature>,
+) -> Option<RGBColor> {
+    if let Some(mirek) = color_temperature
+        .filter(|color_temperature| color_temperature.mirek_valid)
+        .and_then(|color_temperature| color_temperature.mirek)
+    {
+        return Some(RGBColor::from_kelvin(mirek_to_kelvin(mirek)));
+    }
+
+    Some(RGBColor::from_coordinate(
+        color?.xy,
+        gamut?,
+        FULL_BRIGHTNESS,
+    ))
+}
+
+impl Spatial {
+    fn new(layout: SpatialLayout, outputs: &[Output]) -> Spatial {
+        Spatial {
+            maps: outputs
+                .iter()
+                .map(|output| PanelMap::new(&layout, &output.layout))
+                .collect(),
+            streams: outputs.iter().map(|_| None).collect(),
+            colors: vec![None; layout.lights.len()],
+            gamuts: vec![None; layout.lights.len()],
+            layout,
+            streaming: false,
+            has_updated: false,
+        }
+    }
+
+    fn update_lights(&mut self, lights: &[Light]) {
+        for (index, spatial_light) in self.layout.lights.iter().enumerate() {
+            let Some(light) = lights.iter().find(|light| light.id == spatial_light.id) else {
+                continue;
+            };
+
+            let gamut = light.color.as_ref().and_then(|color| color.gamut);
+            // Lights without color support are treated as white.
+            let color = Some(LightColor {
+                color: light_rgb(
+                    gamut,
+                    light.color.as_ref(),
+                    light.color_temperature.as_ref(),
+                )
+                .unwrap_or(RGBColor {
+                    red: 255,
+                    green: 255,
+                    blue: 255,
+                }),
+                brightness: light.brightness(),
+                on: light.on.on,
+            });
+
+            self.gamuts[index] = gamut;
+            if self.colors[index] != color {
+                self.colors[index] = color;
+                self.has_updated = true;
+            }
+        }
+    }
+
+    fn process_light_message(&mut self, id: &str, delta: &LightDelta) {
+        let Some(index) = self.layout.lights.iter().position(|light| light.id == id) else {
+            return;
+        };
+
+        let Some(current) = self.colors[index] else {
+            return;
+        };
+
+        self.colors[index] = Some(LightColor {
+            color: light_rgb(
+                self.gamuts[index],
+                delta.color.as_ref(),
+                delta.color_temperature.as_ref(),
+            )
+            .unwrap_or(current.color),
+            brightness: delta
+                .dimming
+                .as_ref()
+                .map_or(current.brightness, |dimming| dimming.brightness),
+            on: delta.on.as_ref().map_or(current.on, |on| on.on),
+        });
+        self.has_updated = true;
+    }
+
+    /// Map the lights onto an output's panels again after they were moved.
+    fn relayout(&mut self, index: usize, panel_layout: &PanelLayout) {
+        self.maps[index] = PanelMap::new(&self.layout, panel_layout);
+        self.has_updated = true;
+    }
+
+    /// Send the current colors to every output that isn't paused, switching
+    /// them into external control mode first if needed.
+    async fn send(&mut self, outputs: &[Output], paused: &[Option<Instant>], restart: bool) {
+        let colors = &self.colors;
+
+        join_all(
+            outputs
+                .iter()
+                .zip(&self.maps)
+                .zip(self.streams.iter_mut())
+                .zip(paused)
+                .map(|(((output, map), stream), paused)| async move {
+                    if paused.is_some() {
+                        // It has left external control mode by now.
+                        *stream = None;
+                        return;
+                    }
+
+                    if restart || stream.is_none() {
+                        *stream = match output.nanoleaf.start_external_control().await {
+                            Ok(started) => Some(started),
+                            Err(err) => {
+                                handle_nanoleaf_error(output, &err).await;
+                                return;
+                            }
+                        };
+                    }
+
+                    let Some(started) = stream else {
+                        return;
+                    };
+
+                    if let Err(err) = started.send(&map.render(colors)).await {
+                        handle_nanoleaf_error(output, &err).await;
+                        *stream = None;
+                    }
+                }),
+        )
+        .await;
+
+        self.has_updated = false;
+    }
+}
+
+impl Mirror {
+    pub fn new(
+        source: Source,
+        outputs: Vec<Output>,
+        max_brightness: u8,
+        effect: EffectConfig,
+        layout: Option<SpatialLayout>,
+        reverse_sync: bool,
+    ) -> Mirror {
+        Mirror {
+            spatial: layout.map(|layout| Spatial::new(layout, &outputs)),
+            gamuts: HashMap::new(),
+            reverse: reverse_sync.then(ReverseSync::default),
+            paused: vec![None; outputs.len()],
+            source,
+            outputs,
+            effect,
+            room: Room {
+                on: false,
+                brightness: 0.0,
+                dynamic: false,
+                palette: None,
+                color_temperature: None,
+                has_updated: false,
+                scene_has_updated: false,
+                max_brightness,
+            },
+        }
+    }
+
+    pub fn kind(&self) -> SourceKind {
+        match &self.source {
+            Source::Group { kind, .. } => *kind,
+            Source::Light(_) => SourceKind::Light,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.source {
+            Source::Group { group, .. } => &group.metadata.name,
+            Source::Light(light) => &light.metadata.name,
+        }
+    }
+
+    pub fn outputs(&self) -> &[Output] {
+        &self.outputs
+    }
+
+    /// Whether changes made on the outputs are sent back to Hue.
+    pub fn follows_nanoleaf(&self) -> bool {
+        self.reverse.is_some()
+    }
+
+    /// Whether the mirror's layout takes positions from an entertainment area.
+    pub fn follows_entertainment(&self) -> bool {
+        self.spatial
+            .as_ref()
+            .is_some_and(|spatial| spatial.layout.entertainment.is_some())
+    }
+
+    /// Whether the mirror's lights should be sampled, because an app or sync
+    /// box is streaming to its entertainment area.
+    pub fn is_sampling(&self) -> bool {
+        self.spatial
+            .as_ref()
+            .is_some_and(|spatial| spatial.streaming)
+    }
+
+    /// Pick up the current colors of the lights in the layout.
+    pub fn sample(&mut self, lights: &[Light]) {
+        if let Some(spatial) = &mut self.spatial {
+            spatial.update_lights(lights);
+        }
+    }
+
+    /// Build the room's state from scratch by fetching the source's current
+    /// state, and for rooms and zones, their lights and active scene.
+    pub fn sync(&mut self, cache: &ResourceCache) -> Result<(), HueError> {
+        let max_brightness = self.room.max_brightness;
+
+        let room = match &mut self.source {
+            Source::Group {
+                kind,
+                group,
+                grouped_light,
+            } => {
+                // Lights may have been moved around while we weren't looking.
+                let (found, resource) = match kind {
+                    SourceKind::Zone => (cache.zone(&group.id), "zone"),
+                    _ => (cache.room(&group.id), "room"),
+                };
+                *group = found.cloned().ok_or_else(|| HueError::NotFound {
+                    resource,
+                    id: group.id.clone(),
+                })?;
+
+                let (room, lights) = sync_group(
+                    cache,
+                    group,
+                    grouped_light,
+                    max_brightness,
+                    &self.effect.palette,
+                )?;
+                self.gamuts = light_gamuts(&lights);
+
+                if let Some(spatial) = &mut self.spatial {
+                    spatial.update_lights(&lights);
+                    // Colors may be the same as before, but the controller
+                    // still needs a frame after being reset.
+                    spatial.has_updated = true;
+
+                    if let Some(id) = &spatial.layout.entertainment {
+                        spatial.streaming = cache
+                            .entertainment_configuration(id)
+                            .ok_or_else(|| HueError::NotFound {
+                                resource: "entertainment area",
+                                id: id.clone(),
+                            })?
+                            .is_active();
+                    }
+                }
+
+                room
+            }
+            Source::Light(light) => {
+                **light = cache
+                    .light(&light.id)
+                    .cloned()
+                    .ok_or_else(|| HueError::NotFound {
+                        resource: "light",
+                        id: light.id.clone(),
+                    })?;
+
+                let mut room = Room {
+                    on: light.on.on,
+                    brightness: light.brightness(),
+                    dynamic: light.dynamics.status == "dynamic_palette",
+                    palette: None,
+                    color_temperature: None,
+                    has_updated: true,
+                    scene_has_updated: true,
+                    max_brightness,
+                };
+
+                apply_light_color(
+                    &mut room,
+                    light.color.as_ref().and_then(|color| color.gamut),
+                    light.color.as_ref(),
+                    light.color_temperature.as_ref(),
+                );
+
+                room
+            }
+        };
+
+        trace!(target: "nanohue", "Synchronized {} '{}'. {:?}", self.kind(), self.name(), room);
+        self.room = room;
+
+        Ok(())
+    }
+
+    pub fn process_message(&mut self, cache: &ResourceCache, item: &EventMessage) {
+        let room = &mut self.room;
+        // Changes sent over from the Nanoleaf have already been applied.
+        let mut sent = self.reverse.as_mut().map(|reverse| &mut reverse.sent);
+        let mut is_echo = |on: Option<bool>, brightness: Option<f32>| {
+            sent.as_mut().is_some_and(|sent| {
+                on.is_some_and(|on| sent.is_power_echo(on))
+                    || brightness.is_some_and(|brightness| {
+                        sent.is_brightness_echo(brightness.round() as u32)
+                    })
+            })
+        };
+
+        match (&self.source, &item.delta) {
+            (Source::Group { grouped_light, .. }, ResourceDelta::GroupedLight(delta)) => {
+                // It's a grouped light! confirm that it's the right ID.
+                if &item.id != grouped_light {
+                    trace!(target: "nanohue", "Message pertains to a different group. Skipping.");
+                    return;
+                }
+                trace!(target: "nanohue", "Message pertains to the group. {:?}.", item);
+
+                // Check if the on status has changed, and if so, write it to the room.
+                if let Some(on) = delta.on.as_ref().filter(|on| !is_echo(Some(on.on), None)) {
+                    room.on = on.on;
+                    room.has_updated = true
+                }
+
+                // Check if the brightness has changed, and if so, write it to the room.
+                if let Some(dimming) = delta
+                    .dimming
+                    .as_ref()
+                    .filter(|dimming| !is_echo(None, Some(dimming.brightness)))
+                {
+                    room.brightness = dimming.brightness;
+                    room.has_updated = true
+                }
+            }
+            (Source::Group { group, .. }, ResourceDelta::Scene(_)) => {
+                // Check the scene change! If it is part of our group, grab the new palette
+
+                let Some(scene) = cache.scene(&item.id) else {
+                    warn!(target: "nanohue", "Scene {} isn't known to the bridge.", item.id);
+                    return;
+                };
+
+                if scene.group.id != group.id || scene.status.active == "inactive" {
+                    return;
+                }
+
+                apply_scene(scene, room, &self.effect.palette, &self.gamuts);
+            }
+            (Source::Group { .. }, ResourceDelta::Light(delta)) => {
+                if let Some(spatial) = &mut self.spatial {
+                    spatial.process_light_message(&item.id, delta);
+                }
+            }
+            (
+                Source::Group { group, .. },
+                ResourceDelta::EntertainmentConfiguration(EntertainmentConfigurationDelta {
+                    status: Some(status),
+                    ..
+                }),
+            ) => {
+                let Some(spatial) = &mut self.spatial else {
+                    return;
+                };
+
+                if spatial.layout.entertainment.as_ref() != Some(&item.id) {
+                    return;
+                }
+
+                spatial.streaming = status == "active";
+                info!(
+                    target: "nanohue",
+                    "The entertainment area for '{}' is now {}.",
+                    group.metadata.name,
+                    status
+                );
+            }
+            (Source::Light(light), ResourceDelta::Light(delta)) => {
+                if item.id != light.id {
+                    return;
+                }
+                trace!(target: "nanohue", "Message pertains to the light. {:?}.", item);
+
+                if let Some(on) = delta.on.as_ref().filter(|on| !is_echo(Some(on.on), None)) {
+                    room.on = on.on;
+                    room.has_updated = true
+                }
+
+                if let Some(dimming) = delta
+                    .dimming
+                    .as_ref()
+                    .filter(|dimming| !is_echo(None, Some(dimming.brightness)))
+                {
+                    room.brightness = dimming.brightness;
+                    room.has_updated = true
+                }
+
+                if let Some(status) = delta.dynamics.as_ref().and_then(|d| d.status.as_ref()) {
+                    room.dynamic = status == "dynamic_palette";
+                    room.scene_has_updated = true;
+                }
+
+                apply_light_color(
+                    room,
+                    light.color.as_ref().and_then(|color| color.gamut),
+                    delta.color.as_ref(),
+                    delta.color_temperature.as_ref(),
+                );
+            }
+            _ => {}
+        }
+
+        if !self.room.scene_has_updated {
+            return;
+        }
+
+        // The color sent over for a picked effect may come straight back.
+        let window = if self.reverse.is_some() {
+            ECHO_WINDOW
+        } else {
+            Duration::ZERO
+        };
+
+        for (output, paused) in self.outputs.iter().zip(self.paused.iter_mut()) {
+            if paused.is_some_and(|at| at.elapsed() >= window) {
+                info!(target: "nanohue", "Resuming mirroring to the Nanoleaf '{}' for the new colors.", output.name);
+                *paused = None;
+            }
+        }
+    }
+
+    /// React to a change made on one of the outputs, e.g. with its touch
+    /// buttons or the Nanoleaf app. Picking one of the controller's own
+    /// effects pauses mirroring to it until the next scene, and moved panels
+    /// are mapped again.
+    ///
+    /// For mappings with reverse sync, the change is also sent back to Hue.
+    /// Reports of the mirror's own writes are ignored. Effects can't be shown
+    /// by the lights, so they take on its most likely color.
+    pub async fn process_nanoleaf_event(
+        &mut self,
+        hue_client: &Hue,
+        index: usize,
+        event: &NanoleafEvent,
+    ) {
+        let Some(output) = self.outputs.get_mut(index) else {
+            return;
+        };
+
+        match event {
+            NanoleafEvent::Effect(name) if is_saved_effect(name) => {
+                if self.paused[index].is_none() {
+                    info!(target: "nanohue", "The Nanoleaf '{}' is showing '{}', so mirroring to it is paused until the next scene.", output.name, name);
+                }
+                self.paused[index] = Some(Instant::now());
+            }
+            NanoleafEvent::Layout(change) => {
+                match change {
+                    LayoutChange::Layout(layout) => output.layout.layout = layout.clone(),
+                    LayoutChange::GlobalOrientation(orientation) => {
+                        output.layout.global_orientation.value = *orientation
+                    }
+                }
+                info!(target: "nanohue", "The panels on the Nanoleaf '{}' were rearranged.", output.name);
+
+                if let Some(spatial) = &mut self.spatial {
+                    spatial.relayout(index, &output.layout);
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        let Some(reverse) = &mut self.reverse else {
+            return;
+        };
+        let output = &self.outputs[index];
+        let room = &mut self.room;
+        let mut update = LightUpdate::default();
+
+        match event {
+            NanoleafEvent::State(StateChange::On(on)) => {
+                if *on == room.on || reverse.written.is_power_echo(*on) {
+                    return;
+                }
+
+                update.on = Some(OnStatus { on: *on });
+                room.on = *on;
+                // The other outputs should follow too.
+                room.has_updated = true;
+                reverse.origin = Some(index);
+            }
+            NanoleafEvent::State(StateChange::Brightness(brightness)) => {
+                if *brightness == room.get_brightness()
+                    || reverse.written.is_brightness_echo(*brightness)
+                {
+                    return;
+                }
+
+                update.dimming = Some(Dimming {
+                    brightness: *brightness as f32,
+                });
+                room.brightness = *brightness as f32;
+                room.has_updated = true;
+                reverse.origin = Some(index);
+            }
+            NanoleafEvent::Effect(name) if is_saved_effect(name) => {
+                let effect = match output.nanoleaf.request_effect(name).await {
+                    Ok(effect) => effect,
+                    Err(err) => {
+                        warn!(target: "nanohue", "Unable to look up the effect '{}' on the Nanoleaf '{}'. {}", name, output.name, err);
+                        return;
+                    }
+                };
+
+                // The first of the most likely colors.
+                let color = effect.palette.iter().fold(None, |best, color| match best {
+                    Some(best) if probability(best) >= probability(color) => Some(best),
+                    _ => Some(color),
+                });
+                let Some(color) = color else {
+                    return;
+                };
+
+                update.color = Some(ColorUpdate {
+                    xy: color.color.to_rgb().to_coordinate(),
+                });
+            }
+            _ => return,
+        }
+
+        info!(target: "nanohue", "The Nanoleaf '{}' was changed. {:?}", output.name, event);
+
+        reverse.sent.record(
+            update.on.as_ref().map(|on| on.on),
+            update
+                .dimming
+                .as_ref()
+                .map(|dimming| dimming.brightness.round() as u32),
+        );
+
+        let result = match &self.source {
+            Source::Group { grouped_light, .. } => {
+                hue_client.set_grouped_light(grouped_light, &update).await
+            }
+            Source::Light(light) => hue_client.set_light(&light.id, &update).await,
+        };
+
+        if let Err(err) = result {
+            warn!(target: "nanohue", "Unable to send the change to the Hue {} '{}'. {}", self.kind(), self.name(), err);
+        }
+    }
+
+    /// Write any pending changes to every Nanoleaf in the mapping.
+    pub async fn flush(&mut self) {
+        let room_has_updated = self.room.has_updated || self.room.scene_has_updated;
+        let streaming = self.spatial.is_some();
+
+        // A new scene is written everywhere, including to the output a change
+        // came from.
+        let origin = self
+            .reverse
+            .as_mut()
+            .and_then(|reverse| reverse.origin.take())
+            .filter(|_| !self.room.scene_has_updated);
+
+        if room_has_updated {
+            if let Some(reverse) = &mut self.reverse {
+                reverse.written.record(
+                    Some(self.room.on),
+                    self.room.on.then(|| self.room.get_brightness()),
+                );
+            }
+
+            // Paused outputs still follow the room's power and brightness.
+            join_all(
+                self.outputs
+                    .iter()
+                    .zip(&self.paused)
+                    .enumerate()
+                    .filter(|(index, _)| Some(*index) != origin)
+                    .map(|(_, (output, paused))| {
+                        update_nanoleaf(
+                            output,
+                            &self.room,
+                            &self.effect,
+                            !streaming && paused.is_none(),
+                        )
+                    }),
+            )
+            .await;
+        }
+
+        if let Some(spatial) = &mut self.spatial {
+            // A new scene may have come with a new effect, e.g. from the
+            // Nanoleaf app, so make sure the controller is still listening.
+            if self.room.on && (room_has_updated || spatial.has_updated) {
+                spatial
+                    .send(&self.outputs, &self.paused, self.room.scene_has_updated)
+                    .await;
+            }
+        }
+
+        self.room.has_updated = false;
+        self.room.scene_has_updated = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{
+        color::HSVColor,
+        hue::types::HueResponse,
+        spatial::{LayoutMode, SpatialLight},
+        testing::{Reply, StandIn},
+    };
+
+    const LIGHTS: &str = include_str!("../tests/fixtures/hue/lights.json");
+    const SCENES: &str = include_str!("../tests/fixtures/hue/scenes.json");
+    const SCENE_LIGHTS: &str = include_str!("../tests/fixtures/hue/scene_lights.json");
+
+    fn recorded_lights() -> Vec<Light> {
+        serde_json::from_str::<HueResponse<Light>>(LIGHTS)
+            .unwrap()
+            .data
+    }
+
+    fn layout(lights: &[Light]) -> SpatialLayout {
+        SpatialLayout {
+            mode: LayoutMode::Nearest,
+            lights: lights
+                .iter()
+                .map(|light| SpatialLight {
+                    id: light.id.clone(),
+                    position: None,
+                })
+                .collect(),
+            panels: BTreeMap::new(),
+            transition_time: 1,
+            entertainment: None,
+        }
+    }
+
+    #[test]
+    fn samples_recorded_lights() {
+        let lights = recorded_lights();
+        let mut spatial = Spatial::new(layout(&lights), &[]);
+
+        spatial.update_lights(&lights);
+        assert!(spatial.has_updated);
+
+        let colors: Vec<LightColor> = spatial.colors.iter().map(|color| color.unwrap()).collect();
+
+        // A color light, at the red corner of its gamut.
+        assert!(colors[0].color.red > 200);
+        assert!(colors[0].color.green < 50 && colors[0].color.blue < 50);
+        assert_eq!(colors[0].brightness, 80.0);
+        assert!(colors[0].on);
+
+        // A white ambiance light, by its color temperature.
+        assert_eq!(colors[1].color, RGBColor::from_kelvin(mirek_to_kelvin(366)));
+        assert_eq!(colors[1].brightness, 40.0);
+
+        // A white light.
+        assert_eq!(
+            colors[2].color,
+            RGBColor {
+                red: 255,
+                green: 255,
+                blue: 255
+            }
+        );
+        assert!(!colors[2].on);
+    }
+
+    #[test]
+    fn only_flags_changes_when_resampled() {
+        let lights = recorded_lights();
+        let mut spatial = Spatial::new(layout(&lights), &[]);
+        spatial.update_lights(&lights);
+        spatial.has_updated = false;
+
+        spatial.update_lights(&lights);
+        assert!(!spatial.has_updated);
+
+        let mut dimmed = lights.clone();
+        dimmed[1].dimming = Some(Dimming { brightness: 10.0 });
+        spatial.update_lights(&dimmed);
+        assert!(spatial.has_updated);
+        assert_eq!(spatial.colors[1].unwrap().brightness, 10.0);
+    }
+
+    fn output(name: &str, controller: &StandIn) -> Output {
+        let nanoleaf = Nanoleaf::new(String::from("127.0.0.1"), String::from("token"))
+            .unwrap()
+            .with_port(controller.addr().port());
+
+        Output {
+            name: name.to_string(),
+            panel_name: name.to_string(),
+            nanoleaf: Arc::new(nanoleaf),
+            device_id: None,
+            resolving: Arc::default(),
+            layout: serde_json::from_str(
+                r#"{"globalOrientation":{"value":0,"max":360,"min":0},"layout":{"numPanels":0,"sideLength":150,"positionData":[]}}"#,
+            )
+            .unwrap(),
+        }
+    }
+
+    fn bodies(stand_in: &StandIn) -> Vec<String> {
+        stand_in
+            .requests()
+            .into_iter()
+            .map(|request| request.body)
+            .collect()
+    }
+
+    #[test]
+    fn builds_palettes_from_full_intensity_colors() {
+        let mut lights = recorded_lights();
+        let palette = get_palette(&lights, &PaletteConfig::default());
+
+        // The play bar is at 80% and the desk lamp at 40%. Their brightness
+        // decides how much of the palette they get, not how bright their
+        // colors are. The hallway light has no color at all.
+        let probabilities = palette.probabilities();
+        assert_eq!(probabilities.len(), 2);
+        for (color, _) in &probabilities {
+            let rgb = color.to_rgb();
+            assert_eq!(rgb.red.max(rgb.green).max(rgb.blue), 255, "{:?}", rgb);
+        }
+        assert!((probabilities[0].1 - 200.0 / 3.0).abs() < 1e-3);
+        assert!((probabilities[1].1 - 100.0 / 3.0).abs() < 1e-3);
+
+        // Lights with no brightness at all still make a usable palette.
+        for light in &mut lights {
+            light.dimming = Some(Dimming { brightness: 0.0 });
+        }
+        let shares: Vec<f32> = get_palette(&lights, &PaletteConfig::default())
+            .probabilities()
+            .iter()
+            .map(|(_, share)| *share)
+            .collect();
+        assert_eq!(shares, vec![50.0, 50.0]);
+    }
+
+    fn recorded_scenes() -> (Vec<Scene>, Vec<Light>) {
+        let scenes = serde_json::from_str::<HueResponse<Scene>>(SCENES)
+            .unwrap()
+            .data;
+        let lights = serde_json::from_str::<HueResponse<Light>>(SCENE_LIGHTS)
+            .unwrap()
+            .data;
+
+        (scenes, lights)
+    }
+
+    #[test]
+    fn splits_gradients_into_their_points() {
+        let (_, lights) = recorded_scenes();
+        let strip = &lights[0];
+        let gamut = strip.color.as_ref().unwrap().gamut.unwrap();
+        let gradient = strip.gradient.as_ref().unwrap();
+
+        // The strip's brightness is shared between its points.
+        let expected: Vec<(RGBColor, f32)> = gradient
+            .points
+            .iter()
+            .map(|point| {
+                (
+                    RGBColor::from_coordinate(point.color.xy, gamut, FULL_BRIGHTNESS),
+                    20.0,
+                )
+            })
+            .collect();
+        assert_eq!(
+            gradient_colors(Some(gradient), gamut, 60.0),
+            Some(expected.clone())
+        );
+
+        // A light without a gradient is left to its own color.
+        assert_eq!(gradient_colors(None, gamut, 60.0), None);
+        let empty = Gradient { points: Vec::new() };
+        assert_eq!(gradient_colors(Some(&empty), gamut, 60.0), None);
+
+        // A palette taken from the strip itself looks the same.
+        let palette = get_palette(&lights[..1], &PaletteConfig::default());
+        assert_eq!(palette, build_palette(&expected, &PaletteConfig::default()));
+    }
+
+    #[test]
+    fn takes_colors_from_scene_actions_with_each_lights_gamut() {
+        let (scenes, lights) = recorded_scenes();
+        let scene = &scenes[0];
+        let gamuts = light_gamuts(&lights);
+        let strip_gamut = lights[0].color.as_ref().unwrap().gamut.unwrap();
+        let bloom_gamut = lights[1].color.as_ref().unwrap().gamut.unwrap();
+
+        let SceneColors { colors, whites } = scene_colors(scene, &gamuts);
+
+        // The gradient strip gives a color for each point, and the desk lamp
+        // has no brightness of its own in the scene.
+        let gradient = scene.actions[0].action.gradient.as_ref().unwrap();
+        let bloom = scene.actions[1].action.color.as_ref().unwrap().xy;
+        let mut expected: Vec<(RGBColor, f32)> = gradient
+            .points
+            .iter()
+            .map(|point| {
+                (
+                    RGBColor::from_coordinate(point.color.xy, strip_gamut, FULL_BRIGHTNESS),
+                    20.0,
+                )
+            })
+            .collect();
+        expected.push((
+            RGBColor::from_coordinate(bloom, bloom_gamut, FULL_BRIGHTNESS),
+            40.0,
+        ));
+        assert_eq!(colors, expected);
+        assert_eq!(whites, vec![(366, 100.0)]);
+
+        // The Bloom can't show the scene's green. Without its gamut to hand,
+        // the scene would promise a green it can't reach.
+        let SceneColors { colors, .. } = scene_colors(scene, &HashMap::new());
+        let unknown = RGBColor::from_coordinate(bloom, ColorGamut2::default(), FULL_BRIGHTNESS);
+        assert_eq!(colors[3].0, unknown);
+        assert_ne!(unknown, expected[3].0);
+    }
+
+    #[test]
+    fn prefers_a_scenes_own_palette() {
+        let (scenes, lights) = recorded_scenes();
+        let scene = &scenes[1];
+
+        let SceneColors { colors, whites } = scene_colors(scene, &light_gamuts(&lights));
+
+        // Palette colors aren't tied to a light, so they use the widest gamut.
+        let expected: Vec<(RGBColor, f32)> = scene
+            .palette
+            .color
+            .iter()
+            .map(|entry| {
+                (
+                    RGBColor::from_coordinate(
+                        entry.color.xy,
+                        ColorGamut2::default(),
+                        FULL_BRIGHTNESS,
+                    ),
+                    entry.dimming.brightness,
+                )
+            })
+            .collect();
+        assert_eq!(colors, expected);
+        assert_eq!(whites, vec![(153, 50.0)]);
+    }
+
+    #[test]
+    fn mixes_white_and_color_lights_in_a_palette() {
+        let lights = recorded_lights();
+        let color = lights[0].color.as_ref().unwrap();
+
+        let colors: Vec<HSVColor> = get_palette(&lights[..2], &PaletteConfig::default())
+            .probabilities()
+            .into_iter()
+            .map(|(color, _)| color)
+            .collect();
+
+        assert_eq!(
+            colors,
+            vec![
+                RGBColor::from_coordinate(color.xy, color.gamut.unwrap(), FULL_BRIGHTNESS).to_hsv(),
+                RGBColor::from_kelvin(mirek_to_kelvin(366)).to_hsv(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_color_temperatures_within_the_nanoleafs_range() {
+        let controller = StandIn::start(|_| Reply::status(204)).await;
+        let output = output("Shapes", &controller);
+
+        for (mirek, expected) in [(153, 6500), (500, 2000), (1000, 1200)] {
+            let room = Room {
+                on: true,
+                brightness: 50.0,
+                dynamic: false,
+                palette: None,
+                color_temperature: Some(mirek),
+                has_updated: true,
+                scene_has_updated: true,
+                max_brightness: 100,
+            };
+            write_room_to_nanoleaf(&output.nanoleaf, &room, &EffectConfig::default(), true)
+                .await
+                .unwrap();
+
+            let request = controller.requests().pop().unwrap();
+            assert!(request.path.ends_with("/state/ct"));
+            assert_eq!(
+                request.body,
+                format!(r#"{{"ct":{{"value":{}}}}}"#, expected)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn leaves_the_nanoleaf_a_change_came_from_alone() {
+        let bridge =
+            StandIn::start(|_| Reply::Json(200, String::from(r#"{"data":[],"errors":[]}"#))).await;
+        let changed = StandIn::start(|_| Reply::status(200)).await;
+        let other = StandIn::start(|_| Reply::status(200)).await;
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+
+        let light = recorded_lights().remove(1);
+        let light_id = light.id.clone();
+        let mut mirror = Mirror::new(
+            Source::Light(Box::new(light)),
+            vec![output("changed", &changed), output("other", &other)],
+            60,
+            EffectConfig::default(),
+            None,
+            true,
+        );
+
+        // Turned on and brightened past the mapping's limit on the Nanoleaf.
+        for change in [StateChange::On(true), StateChange::Brightness(90)] {
+            mirror
+                .process_nanoleaf_event(&hue, 0, &NanoleafEvent::State(change))
+                .await;
+            mirror.flush().await;
+        }
+
+        assert_eq!(
+            bodies(&bridge),
+            vec![
+                r#"{"on":{"on":true}}"#,
+                r#"{"dimming":{"brightness":90.0}}"#
+            ]
+        );
+        // The Nanoleaf isn't pulled back down to the limit.
+        assert!(changed.requests().is_empty());
+        // The other one follows, within the limit.
+        assert_eq!(
+            bodies(&other).last().map(String::as_str),
+            Some(r#"{"brightness":{"value":60,"duration":1}}"#)
+        );
+
+        // Changes made in Hue still reach both.
+        let message = EventMessage {
+            id: light_id,
+            owner: None,
+            delta: ResourceDelta::Light(LightDelta {
+                dimming: Some(Dimming { brightness: 30.0 }),
+                ..LightDelta::default()
+            }),
+        };
+        mirror.process_message(&ResourceCache::default(), &message);
+        mirror.flush().await;
+
+        for controller in [&changed, &other] {
+            assert_eq!(
+                bodies(controller).last().map(String::as_str),
+                Some(r#"{"brightness":{"value":30,"duration":1}}"#)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn looks_an_unreachable_nanoleaf_up_in_the_background() {
+        let controller = StandIn::start(|_| Reply::status(200)).await;
+        let mut output = output("Shapes", &controller);
+        output.device_id = Some(String::from("AA:BB:CC:DD:EE:FF"));
+        let err = NanoleafError::Status {
+            status: reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            body: String::new(),
+        };
+
+        // Resolving browses the network for several seconds, which mustn't
+        // hold up the caller.
+        tokio::time::timeout(
+            Duration::from_millis(500),
+            handle_nanoleaf_error(&output, &err),
+        )
+        .await
+        .unwrap();
+        assert!(output.resolving.load(Ordering::SeqCst));
+
+        // A second failure while the lookup is running doesn't start another.
+        tokio::time::timeout(
+            Duration::from_millis(500),
+            handle_nanoleaf_error(&output, &err),
+        )
+        .await
+        .unwrap();
+        assert!(output.resolving.load(Ordering::SeqCst));
+    }
+}
@@ -1,2 +1,5 @@
 pub mod client;
+pub mod error;
+pub mod eventstream;
+pub mod stream;
 pub mod types;
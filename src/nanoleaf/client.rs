@@ -1,11 +1,28 @@
-use log::trace;
-use reqwest::Response;
+use std::{ops::RangeInclusive, sync::RwLock};
+
+use log::{info, trace};
+use reqwest::{Response, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use super::types::{BoolValue, Effect, Panel, TransitionValue};
+use super::{
+    error::NanoleafError,
+    eventstream::EventStream,
+    stream::{ExternalControl, STREAM_PORT},
+    types::{AuthToken, BoolValue, Effect, EffectDetails, EventType, Panel, TransitionValue},
+};
+
+/// The color temperatures controllers accept, in Kelvin.
+pub const COLOR_TEMPERATURE_RANGE: RangeInclusive<u32> = 1200..=6500;
+
+/// The port controllers serve their API on.
+const API_PORT: u16 = 16021;
+
 pub struct Nanoleaf {
-    base_url: String,
+    api_token: String,
+    hostname: RwLock<String>,
+    port: u16,
     client: reqwest::Client,
+    dry_run: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,6 +45,12 @@ struct ColorTemperatureUpdate {
     ct: Value<u32>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct ColorUpdate {
+    hue: Value<u32>,
+    sat: Value<u32>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct EffectUpdate {
     write: Effect,
@@ -38,21 +61,103 @@ struct EffectSelect {
     select: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct EffectRequestCommand {
+    command: String,
+    #[serde(rename = "animName")]
+    animation_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EffectRequest {
+    write: EffectRequestCommand,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExternalControlCommand {
+    command: String,
+    #[serde(rename = "animType")]
+    animation_type: String,
+    #[serde(rename = "extControlVersion")]
+    version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExternalControlUpdate {
+    write: ExternalControlCommand,
+}
+
 impl Nanoleaf {
-    pub fn new(hostname: String, api_token: String) -> Result<Nanoleaf, reqwest::Error> {
+    pub fn new(hostname: String, api_token: String) -> Result<Nanoleaf, NanoleafError> {
         let client = reqwest::Client::builder().build()?;
-        let base_url = format!("http://{}:16021/api/v1/{}", hostname, api_token);
         Ok(Nanoleaf {
             client,
-            base_url: base_url,
+            api_token,
+            hostname: RwLock::new(hostname),
+            port: API_PORT,
+            dry_run: false,
         })
     }
 
-    async fn put<T>(&self, url: &str, payload: &T) -> Result<Response, Box<dyn std::error::Error>>
+    /// Talk to a stand-in controller on another port.
+    #[cfg(test)]
+    pub fn with_port(mut self, port: u16) -> Nanoleaf {
+        self.port = port;
+        self
+    }
+
+    /// Log updates instead of sending them to the controller.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Nanoleaf {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Point the client at a different address, e.g. after the controller was
+    /// given a new DHCP lease.
+    pub fn set_host(&self, hostname: &str) {
+        *self.hostname.write().unwrap() = hostname.to_string();
+    }
+
+    fn base_url(&self) -> String {
+        format!(
+            "http://{}:{}/api/v1/{}",
+            self.hostname.read().unwrap(),
+            self.port,
+            self.api_token
+        )
+    }
+
+    /// Request a new auth token. The controller only hands one out while it is in
+    /// pairing mode, i.e. for 30 seconds after holding down the power button.
+    pub async fn pair(hostname: &str) -> Result<String, NanoleafError> {
+        let client = reqwest::Client::builder().build()?;
+        let url = format!("http://{}:{}/api/v1/new", hostname, API_PORT);
+
+        trace!(target: "nanoleaf", "POST {:?}", url);
+        let response = client.post(&url).send().await?;
+
+        if response.status() == StatusCode::FORBIDDEN {
+            return Err(NanoleafError::NotInPairingMode);
+        }
+
+        let response = check_status(&url, response).await?;
+        let body = response.text().await?;
+        let token = serde_json::from_str::<AuthToken>(&body)?;
+
+        Ok(token.auth_token)
+    }
+
+    async fn put<T>(&self, url: &str, payload: &T) -> Result<(), NanoleafError>
     where
         T: Serialize,
     {
         let body = serde_json::to_string(payload)?;
+
+        if self.dry_run {
+            info!(target: "nanoleaf", "Dry run, skipping PUT {:?} {}", url, body);
+            return Ok(());
+        }
+
         trace!(target: "nanoleaf", "Creating PUT payload: {:?}", body);
         let response = self
             .client
@@ -60,9 +165,7 @@ impl Nanoleaf {
             .header(reqwest::header::CONTENT_TYPE, "application/json")
             .body(body)
             .send()
-            .await
-            .map_err(|err| panic!("Failed to get result. {:?}", err))
-            .unwrap();
+            .await?;
 
         trace!(
             target: "nanoleaf",
@@ -70,78 +173,200 @@ impl Nanoleaf {
             response.status().to_string()
         );
 
-        Ok(response)
-    }
+        check_status(url, response).await?;
 
-    #[allow(unused)]
-    pub async fn get_panel(&self) -> Result<Panel, reqwest::Error> {
-        let response = self.client.get(&(self.base_url)).send().await?;
+        Ok(())
+    }
 
-        let json_response: Panel = response.json::<Panel>().await?;
+    pub async fn get_panel(&self) -> Result<Panel, NanoleafError> {
+        let base_url = self.base_url();
+        let response = self.client.get(&base_url).send().await?;
+        let response = check_status(&base_url, response).await?;
 
-        Ok(json_response)
+        let body = response.text().await?;
+        Ok(serde_json::from_str::<Panel>(&body)?)
     }
 
-    pub async fn set_power(&self, value: bool) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn set_power(&self, value: bool) -> Result<(), NanoleafError> {
         let payload = PowerUpdate {
-            on: BoolValue { value: value },
+            on: BoolValue { value },
         };
 
-        let url = format!("{}/state", self.base_url);
+        let url = format!("{}/state", self.base_url());
 
-        let _response = self.put(&url, &payload).await?;
+        self.put(&url, &payload).await?;
         Ok(())
     }
 
-    pub async fn set_brightness(
-        &self,
-        value: u32,
-        duration: u32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn set_brightness(&self, value: u32, duration: u32) -> Result<(), NanoleafError> {
         let payload = BrightnessUpdate {
             brightness: TransitionValue {
-                value: if value <= 100 { value } else { 100 },
+                value: value.min(100),
                 duration,
             },
         };
 
         trace!(target: "nanoleaf", "Setting the brightness to {} over the next {} seconds.", value, duration);
-        let url = format!("{}/state/brightness", self.base_url);
+        let url = format!("{}/state/brightness", self.base_url());
 
-        let _response = self.put(&url, &payload).await?;
+        self.put(&url, &payload).await?;
 
         Ok(())
     }
 
-    pub async fn set_color_temperature(
-        &self,
-        value: u32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        assert!(value >= 1200);
-        assert!(value <= 6500);
+    pub async fn set_color_temperature(&self, value: u32) -> Result<(), NanoleafError> {
+        if !COLOR_TEMPERATURE_RANGE.contains(&value) {
+            return Err(NanoleafError::OutOfRange {
+                name: "color temperature",
+                value,
+                min: *COLOR_TEMPERATURE_RANGE.start(),
+                max: *COLOR_TEMPERATURE_RANGE.end(),
+            });
+        }
 
         let payload = ColorTemperatureUpdate {
             ct: Value::<u32> { value },
         };
 
         trace!(target: "nanoleaf", "Setting the color temperature to {}.", value);
-        let url = format!("{}/state/ct", self.base_url);
+        let url = format!("{}/state/ct", self.base_url());
+
+        self.put(&url, &payload).await?;
+
+        Ok(())
+    }
+
+    /// Show a solid color, given as a hue from 0 to 360 and a saturation
+    /// from 0 to 100.
+    pub async fn set_color(&self, hue: u32, saturation: u32) -> Result<(), NanoleafError> {
+        let payload = ColorUpdate {
+            hue: Value::<u32> {
+                value: hue.min(360),
+            },
+            sat: Value::<u32> {
+                value: saturation.min(100),
+            },
+        };
 
-        let _response = self.put(&url, &payload).await?;
+        trace!(target: "nanoleaf", "Setting the color to hue {} and saturation {}.", hue, saturation);
+        let url = format!("{}/state", self.base_url());
+
+        self.put(&url, &payload).await?;
 
         Ok(())
     }
 
-    pub async fn write_effect(&self, effect: Effect) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn write_effect(&self, effect: Effect) -> Result<(), NanoleafError> {
         // Write an effect to the Nanoleaf, and set it as the active effect.
 
         trace!(target: "nanoleaf", "Writing effect {:?}.", effect);
-        let url = format!("{}/effects", self.base_url);
+        let url = format!("{}/effects", self.base_url());
 
         let payload = EffectUpdate { write: effect };
 
-        let _response = self.put(&url, &payload).await?;
+        self.put(&url, &payload).await?;
 
         Ok(())
     }
+
+    pub async fn select_effect(&self, name: &str) -> Result<(), NanoleafError> {
+        trace!(target: "nanoleaf", "Selecting effect {:?}.", name);
+        let url = format!("{}/effects", self.base_url());
+
+        let payload = EffectSelect {
+            select: name.to_string(),
+        };
+
+        self.put(&url, &payload).await?;
+
+        Ok(())
+    }
+
+    /// Look up a saved effect, e.g. to find out which colors it uses.
+    pub async fn request_effect(&self, name: &str) -> Result<EffectDetails, NanoleafError> {
+        let url = format!("{}/effects", self.base_url());
+        let payload = EffectRequest {
+            write: EffectRequestCommand {
+                command: String::from("request"),
+                animation_name: name.to_string(),
+            },
+        };
+
+        // Nothing is changed, so this is sent even during a dry run.
+        trace!(target: "nanoleaf", "Requesting effect {:?}.", name);
+        let response = self.client.put(&url).json(&payload).send().await?;
+        let response = check_status(&url, response).await?;
+
+        let body = response.text().await?;
+        Ok(serde_json::from_str::<EffectDetails>(&body)?)
+    }
+
+    /// Subscribe to changes made on the controller. The stream ends if the
+    /// connection drops, so it's up to the caller to open a new one.
+    pub async fn events(&self, types: &[EventType]) -> Result<EventStream, NanoleafError> {
+        let ids: Vec<String> = types
+            .iter()
+            .map(|event_type| (*event_type as u8).to_string())
+            .collect();
+        let url = format!("{}/events?id={}", self.base_url(), ids.join(","));
+        trace!(target: "nanoleaf", "Opening event stream {:?}", url);
+
+        let response = self
+            .client
+            .get(&url)
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+        let response = check_status(&url, response).await?;
+
+        Ok(EventStream::new(response.bytes_stream()))
+    }
+
+    /// Switch the controller into external control mode, after which each panel
+    /// can be set individually by streaming frames to it over UDP. The mode lasts
+    /// until another effect is selected.
+    pub async fn start_external_control(&self) -> Result<ExternalControl, NanoleafError> {
+        let url = format!("{}/effects", self.base_url());
+
+        let payload = ExternalControlUpdate {
+            write: ExternalControlCommand {
+                command: String::from("display"),
+                animation_type: String::from("extControl"),
+                version: String::from("v2"),
+            },
+        };
+
+        self.put(&url, &payload).await?;
+
+        let hostname = self.hostname.read().unwrap().clone();
+        let address = tokio::net::lookup_host((hostname.as_str(), STREAM_PORT))
+            .await?
+            .next()
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("unable to resolve {:?}", hostname),
+                )
+            })?;
+
+        trace!(target: "nanoleaf", "Streaming to {}.", address);
+        ExternalControl::connect(address, self.dry_run).await
+    }
+}
+
+async fn check_status(url: &str, response: Response) -> Result<Response, NanoleafError> {
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(NanoleafError::Unauthorized),
+        StatusCode::NOT_FOUND => Err(NanoleafError::NotFound(url.to_string())),
+        _ => {
+            let body = response.text().await.unwrap_or_default();
+            Err(NanoleafError::Status { status, body })
+        }
+    }
 }
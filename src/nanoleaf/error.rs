@@ -0,0 +1,45 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum NanoleafError {
+    #[error("unable to reach the Nanoleaf controller: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    #[error("the Nanoleaf controller rejected the auth token")]
+    Unauthorized,
+
+    #[error("the Nanoleaf controller responded with {status}: {body}")]
+    Status { status: StatusCode, body: String },
+
+    #[error("unable to decode the Nanoleaf response: {0}")]
+    Decode(#[from] serde_json::Error),
+
+    #[error("unable to stream to the Nanoleaf controller: {0}")]
+    Stream(#[from] std::io::Error),
+
+    #[error("the Nanoleaf controller is not in pairing mode")]
+    NotInPairingMode,
+
+    #[error("{0:?} was not found on the Nanoleaf controller")]
+    NotFound(String),
+
+    #[error("{name} must be within {min}..={max}, got {value}")]
+    OutOfRange {
+        name: &'static str,
+        value: u32,
+        min: u32,
+        max: u32,
+    },
+}
+
+impl NanoleafError {
+    /// Whether the request that produced this error is worth repeating later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            NanoleafError::Transport(_) | NanoleafError::Stream(_) => true,
+            NanoleafError::Status { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use log::{trace, warn};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::sse::SseParser;
+
+use super::types::{
+    EventAttribute, EventFrame, EventType, Gesture, LayoutChange, NanoleafEvent, StateChange,
+    TouchAttribute,
+};
+
+type ByteStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+/// A long-lived connection to a controller's `/events` endpoint.
+///
+/// Each SSE frame is tagged with the kind of event in its `id`, and carries
+/// one or more changed attributes, which are yielded one at a time. The stream
+/// ends when the connection is closed or errors.
+pub struct EventStream {
+    body: ByteStream,
+    parser: SseParser,
+    pending: VecDeque<NanoleafEvent>,
+}
+
+impl EventStream {
+    pub fn new<S>(body: S) -> EventStream
+    where
+        S: Stream<Item = reqwest::Result<Bytes>> + Send + 'static,
+    {
+        EventStream {
+            body: Box::pin(body),
+            parser: SseParser::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn process_chunk(&mut self, chunk: &[u8]) {
+        for frame in self.parser.feed(chunk) {
+            let Some(event_type) = frame.id.as_deref().and_then(EventType::from_id) else {
+                trace!(target: "nanoleaf", "Skipping event {:?}. {:?}", frame.id, frame.data);
+                continue;
+            };
+
+            match serde_json::from_str::<EventFrame>(&frame.data) {
+                Ok(events) => self.pending.extend(
+                    events
+                        .events
+                        .into_iter()
+                        .filter_map(|entry| parse_event(event_type, entry)),
+                ),
+                Err(err) => warn!(
+                    target: "nanoleaf",
+                    "Unable to parse event stream frame. {:?} {:?}",
+                    err,
+                    frame.data
+                ),
+            }
+        }
+    }
+}
+
+/// Turn an entry in an event into its typed form. Entries we don't know about
+/// are skipped, so that newer firmware doesn't break the stream.
+fn parse_event(event_type: EventType, entry: Value) -> Option<NanoleafEvent> {
+    if event_type == EventType::Touch {
+        let touch: TouchAttribute = parse_entry(event_type, entry)?;
+        return Some(NanoleafEvent::Touch {
+            panel_id: u16::try_from(touch.panel_id).ok(),
+            gesture: Gesture::from(touch.gesture),
+        });
+    }
+
+    let attribute: EventAttribute = parse_entry(event_type, entry)?;
+    let value = &attribute.value;
+    let number = |value: &Value| value.as_u64().map(|value| value as u32);
+
+    let event = match (event_type, attribute.attr) {
+        (EventType::State, 1) => NanoleafEvent::State(StateChange::On(value.as_bool()?)),
+        (EventType::State, 2) => NanoleafEvent::State(StateChange::Brightness(number(value)?)),
+        (EventType::State, 3) => NanoleafEvent::State(StateChange::Hue(number(value)?)),
+        (EventType::State, 4) => NanoleafEvent::State(StateChange::Saturation(number(value)?)),
+        (EventType::State, 5) => {
+            NanoleafEvent::State(StateChange::ColorTemperature(number(value)?))
+        }
+        (EventType::State, 6) => {
+            NanoleafEvent::State(StateChange::ColorMode(value.as_str()?.to_string()))
+        }
+        (EventType::Layout, 1) => NanoleafEvent::Layout(LayoutChange::Layout(parse_entry(
+            event_type,
+            attribute.value,
+        )?)),
+        // Sometimes sent as a bare number, and sometimes as a range.
+        (EventType::Layout, 2) => NanoleafEvent::Layout(LayoutChange::GlobalOrientation(
+            number(value).or_else(|| number(value.get("value")?))?,
+        )),
+        (EventType::Effects, 1) => NanoleafEvent::Effect(value.as_str()?.to_string()),
+        _ => {
+            trace!(target: "nanoleaf", "Skipping attribute {:?} of {:?}.", attribute, event_type);
+            return None;
+        }
+    };
+
+    Some(event)
+}
+
+fn parse_entry<T: DeserializeOwned>(event_type: EventType, entry: Value) -> Option<T> {
+    match serde_json::from_value(entry) {
+        Ok(parsed) => Some(parsed),
+        Err(err) => {
+            warn!(target: "nanoleaf", "Unable to parse {:?} event. {}", event_type, err);
+            None
+        }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = NanoleafEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+
+            match self.body.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(chunk))) => self.process_chunk(&chunk),
+                Poll::Ready(Some(Err(err))) => {
+                    warn!(target: "nanoleaf", "Event stream connection failed. {:?}", err);
+                    return Poll::Ready(None);
+                }
+                Poll::Ready(None) => {
+                    trace!(target: "nanoleaf", "Event stream closed by the controller.");
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::stream;
+
+    use super::*;
+    use crate::{
+        nanoleaf::client::Nanoleaf,
+        testing::{Reply, StandIn},
+    };
+
+    const EVENTS: &str = include_str!("../../tests/fixtures/nanoleaf/events.txt");
+
+    /// Split the recorded stream into uneven chunks, so that frames and lines
+    /// arrive in pieces.
+    fn chunks(body: &str) -> Vec<reqwest::Result<Bytes>> {
+        body.as_bytes()
+            .chunks(37)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect()
+    }
+
+    fn assert_recorded_events(events: &[NanoleafEvent]) {
+        assert_eq!(events.len(), 15, "{:#?}", events);
+
+        let states: Vec<&StateChange> = events
+            .iter()
+            .filter_map(|event| match event {
+                NanoleafEvent::State(change) => Some(change),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                &StateChange::On(true),
+                &StateChange::Brightness(65),
+                &StateChange::ColorMode(String::from("hs")),
+                &StateChange::Hue(212),
+                &StateChange::Saturation(80),
+                &StateChange::ColorMode(String::from("ct")),
+                &StateChange::ColorTemperature(2700),
+            ]
+        );
+
+        let NanoleafEvent::Layout(LayoutChange::Layout(layout)) = &events[7] else {
+            panic!("expected a layout, got {:?}", events[7]);
+        };
+        assert_eq!(layout.num_panels, 3);
+        assert_eq!(layout.side_length, 100);
+        let panels: Vec<(u16, i32, i32, i32)> = layout
+            .position_data
+            .iter()
+            .map(|panel| (panel.panel_id, panel.x, panel.y, panel.orientation))
+            .collect();
+        assert_eq!(
+            panels,
+            vec![(18514, 50, 58, 240), (42129, 100, 29, 60), (0, 75, 0, 0)]
+        );
+
+        // Both forms of the orientation.
+        assert!(matches!(
+            events[8],
+            NanoleafEvent::Layout(LayoutChange::GlobalOrientation(90))
+        ));
+        assert!(matches!(
+            events[9],
+            NanoleafEvent::Layout(LayoutChange::GlobalOrientation(120))
+        ));
+
+        assert!(matches!(&events[10], NanoleafEvent::Effect(name) if name == "Northern Lights"));
+
+        let touches: Vec<(Option<u16>, Gesture)> = events[11..]
+            .iter()
+            .map(|event| match event {
+                NanoleafEvent::Touch { panel_id, gesture } => (*panel_id, *gesture),
+                event => panic!("expected a touch, got {:?}", event),
+            })
+            .collect();
+        assert_eq!(
+            touches,
+            vec![
+                (Some(18514), Gesture::SingleTap),
+                (Some(42129), Gesture::DoubleTap),
+                (None, Gesture::SwipeLeft),
+                (None, Gesture::Other(7)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_recorded_events() {
+        let events: Vec<NanoleafEvent> = EventStream::new(stream::iter(chunks(EVENTS)))
+            .collect()
+            .await;
+
+        assert_recorded_events(&events);
+    }
+
+    #[tokio::test]
+    async fn skips_frames_that_cant_be_parsed() {
+        let body = "id: 1\ndata: {\"events\":\n\nid: 1\ndata: {\"events\":[{\"attr\":2,\"value\":\"bright\"},{\"attr\":2,\"value\":40}]}\n\n";
+
+        let events: Vec<NanoleafEvent> =
+            EventStream::new(stream::iter(chunks(body))).collect().await;
+
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0],
+            NanoleafEvent::State(StateChange::Brightness(40))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reads_events_from_a_stand_in_controller() {
+        let recorded: Vec<&str> = EVENTS.split_inclusive("\n\n").collect();
+        let controller = StandIn::start(move |_| Reply::events(&recorded)).await;
+        let nanoleaf = Nanoleaf::new(String::from("127.0.0.1"), String::from("token"))
+            .unwrap()
+            .with_port(controller.addr().port());
+
+        let events: Vec<NanoleafEvent> = nanoleaf
+            .events(&EventType::ALL)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        assert_recorded_events(&events);
+
+        let requests = controller.requests();
+        assert_eq!(requests[0].path, "/api/v1/token/events?id=1,2,3,4");
+        assert_eq!(requests[0].header("accept"), Some("text/event-stream"));
+    }
+}
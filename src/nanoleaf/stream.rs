@@ -0,0 +1,190 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use log::{info, trace};
+use tokio::net::UdpSocket;
+
+use super::error::NanoleafError;
+
+/// The port controllers listen on for external control frames.
+pub const STREAM_PORT: u16 = 60222;
+
+/// The controller can't keep up with frames sent much faster than this.
+pub const MIN_FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The color of a single panel within a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanelFrame {
+    pub panel_id: u16,
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub white: u8,
+    /// How long to fade to the new color, in tenths of a second.
+    pub transition_time: u16,
+}
+
+/// Encode a frame using the v2 external control protocol. Every value is
+/// big-endian: the number of panels, followed by each panel's ID, red, green,
+/// blue and white channels, and transition time.
+pub fn encode_frame(panels: &[PanelFrame]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(2 + panels.len() * 8);
+    frame.extend_from_slice(&(panels.len() as u16).to_be_bytes());
+
+    for panel in panels {
+        frame.extend_from_slice(&panel.panel_id.to_be_bytes());
+        frame.extend_from_slice(&[panel.red, panel.green, panel.blue, panel.white]);
+        frame.extend_from_slice(&panel.transition_time.to_be_bytes());
+    }
+
+    frame
+}
+
+/// A UDP stream of per-panel colors to a controller in external control mode.
+/// Frames are paced to `MIN_FRAME_INTERVAL`, waiting before sending if needed.
+pub struct ExternalControl {
+    socket: UdpSocket,
+    address: SocketAddr,
+    last_sent: Option<Instant>,
+    dry_run: bool,
+}
+
+impl ExternalControl {
+    pub async fn connect(
+        address: SocketAddr,
+        dry_run: bool,
+    ) -> Result<ExternalControl, NanoleafError> {
+        let local: SocketAddr = if address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0_u16; 8], 0).into()
+        };
+
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(address).await?;
+
+        Ok(ExternalControl {
+            socket,
+            address,
+            last_sent: None,
+            dry_run,
+        })
+    }
+
+    pub async fn send(&mut self, panels: &[PanelFrame]) -> Result<(), NanoleafError> {
+        if let Some(last_sent) = self.last_sent {
+            let ready_at = last_sent + MIN_FRAME_INTERVAL;
+            tokio::time::sleep_until(ready_at.into()).await;
+        }
+
+        let frame = encode_frame(panels);
+        self.last_sent = Some(Instant::now());
+
+        if self.dry_run {
+            info!(target: "nanoleaf", "Dry run, skipping frame to {} {:?}", self.address, panels);
+            return Ok(());
+        }
+
+        trace!(target: "nanoleaf", "Sending a frame of {} panels to {}.", panels.len(), self.address);
+        self.socket.send(&frame).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panel(panel_id: u16, red: u8, transition_time: u16) -> PanelFrame {
+        PanelFrame {
+            panel_id,
+            red,
+            green: 0x20,
+            blue: 0x30,
+            white: 0,
+            transition_time,
+        }
+    }
+
+    #[test]
+    fn encodes_each_panel_big_endian() {
+        let frame = encode_frame(&[panel(0x1234, 0x10, 1), panel(7, 0xff, 0x0102)]);
+
+        assert_eq!(
+            frame,
+            vec![
+                0x00, 0x02, // panels
+                0x12, 0x34, 0x10, 0x20, 0x30, 0x00, 0x00, 0x01, // first panel
+                0x00, 0x07, 0xff, 0x20, 0x30, 0x00, 0x01, 0x02, // second panel
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_paced_frames_to_the_controller() {
+        let controller = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = ExternalControl::connect(controller.local_addr().unwrap(), false)
+            .await
+            .unwrap();
+
+        let frames = [
+            vec![panel(101, 1, 0), panel(202, 2, 0)],
+            vec![panel(101, 3, 5)],
+            vec![panel(101, 4, 10), panel(202, 5, 10), panel(303, 6, 10)],
+        ];
+
+        let sender = tokio::spawn(async move {
+            for panels in &frames {
+                stream.send(panels).await.unwrap();
+            }
+        });
+
+        let mut received = Vec::new();
+        let mut buffer = [0; 1024];
+        for _ in 0..3 {
+            let length = controller.recv(&mut buffer).await.unwrap();
+            received.push((Instant::now(), buffer[..length].to_vec()));
+        }
+        sender.await.unwrap();
+
+        let counts: Vec<u16> = received
+            .iter()
+            .map(|(_, frame)| u16::from_be_bytes([frame[0], frame[1]]))
+            .collect();
+        assert_eq!(counts, vec![2, 1, 3]);
+
+        let (_, last) = &received[2];
+        assert_eq!(last.len(), 2 + 3 * 8);
+        assert_eq!(
+            &last[2 + 2 * 8..],
+            &[0x01, 0x2f, 0x06, 0x20, 0x30, 0x00, 0x00, 0x0a]
+        );
+
+        for pair in received.windows(2) {
+            let gap = pair[1].0 - pair[0].0;
+            assert!(
+                gap >= MIN_FRAME_INTERVAL - Duration::from_millis(10),
+                "{:?}",
+                gap
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_nothing_on_a_dry_run() {
+        let controller = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = ExternalControl::connect(controller.local_addr().unwrap(), true)
+            .await
+            .unwrap();
+
+        stream.send(&[panel(101, 1, 0)]).await.unwrap();
+
+        let mut buffer = [0; 64];
+        let received =
+            tokio::time::timeout(Duration::from_millis(100), controller.recv(&mut buffer)).await;
+        assert!(received.is_err());
+    }
+}
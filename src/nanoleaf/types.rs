@@ -2,11 +2,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::color::HSVColor;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RangeValue {
-    value: u32,
-    max: u32,
-    min: u32,
+    pub value: u32,
+    pub max: u32,
+    pub min: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -17,7 +17,7 @@ pub struct Range {
     pub max: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BoolValue {
     pub value: bool,
 }
@@ -29,20 +29,103 @@ pub struct TransitionValue {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct AuthToken {
+    pub auth_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PanelState {
-    brightness: RangeValue,
+    pub brightness: RangeValue,
     #[serde(rename = "colorMode")]
-    color_mode: String,
-    hue: RangeValue,
-    sat: RangeValue,
-    ct: RangeValue,
-    on: BoolValue,
+    pub color_mode: String,
+    pub hue: RangeValue,
+    pub sat: RangeValue,
+    pub ct: RangeValue,
+    pub on: BoolValue,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Effects {
+    #[serde(rename = "effectsList")]
+    pub effects_list: Vec<String>,
+    pub select: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PanelPosition {
+    #[serde(rename = "panelId")]
+    pub panel_id: u16,
+    pub x: i32,
+    pub y: i32,
+    #[serde(rename = "o")]
+    pub orientation: i32,
+    #[serde(rename = "shapeType")]
+    pub shape_type: u32,
+}
+
+impl PanelPosition {
+    /// Whether the panel has LEDs of its own, rather than being a controller,
+    /// connector or power supply.
+    pub fn is_lit(&self) -> bool {
+        !matches!(self.shape_type, 1 | 5 | 12 | 16 | 19 | 20)
+    }
+
+    pub fn shape_name(&self) -> &'static str {
+        match self.shape_type {
+            0 => "Triangle",
+            1 => "Rhythm",
+            2 => "Square",
+            3 => "Control Square Primary",
+            4 => "Control Square Passive",
+            5 => "Power Supply",
+            7 => "Hexagon (Shapes)",
+            8 => "Triangle (Shapes)",
+            9 => "Mini Triangle (Shapes)",
+            12 => "Shapes Controller",
+            14 => "Elements Hexagon",
+            15 => "Elements Hexagon Corner",
+            16 => "Lines Connector",
+            17 => "Light Lines",
+            18 => "Light Lines Single Zone",
+            19 => "Controller Cap",
+            20 => "Power Connector",
+            _ => "Unknown",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Layout {
+    #[serde(rename = "numPanels")]
+    pub num_panels: u32,
+    #[serde(rename = "sideLength")]
+    pub side_length: u32,
+    #[serde(rename = "positionData")]
+    pub position_data: Vec<PanelPosition>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PanelLayout {
+    #[serde(rename = "globalOrientation")]
+    pub global_orientation: RangeValue,
+    pub layout: Layout,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Panel {
-    name: String,
-    state: PanelState,
+    pub name: String,
+    #[serde(rename = "serialNo")]
+    pub serial_number: String,
+    pub manufacturer: String,
+    #[serde(rename = "firmwareVersion")]
+    pub firmware_version: String,
+    #[serde(rename = "hardwareVersion")]
+    pub hardware_version: Option<String>,
+    pub model: String,
+    pub effects: Effects,
+    #[serde(rename = "panelLayout")]
+    pub panel_layout: PanelLayout,
+    pub state: PanelState,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -72,5 +155,152 @@ pub struct Effect {
     #[serde(rename = "delayTime")]
     pub delay_time: Range,
 
-    pub palette: Vec<HSVColor>,
+    pub palette: Vec<PaletteColor>,
+}
+
+/// A color in an effect's palette. `probability` is how likely the color is to
+/// be picked next, as a percentage.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PaletteColor {
+    #[serde(flatten)]
+    pub color: HSVColor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probability: Option<f32>,
+}
+
+/// An effect as the controller describes it. Plugin effects carry many more
+/// settings, but only the palette is of interest here.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EffectDetails {
+    #[serde(rename = "animName")]
+    pub animation_name: String,
+    #[serde(default)]
+    pub palette: Vec<PaletteColor>,
+}
+
+// Events
+
+/// The kinds of event a controller can report, numbered as they are when
+/// subscribing to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventType {
+    State = 1,
+    Layout = 2,
+    Effects = 3,
+    Touch = 4,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 4] = [
+        EventType::State,
+        EventType::Layout,
+        EventType::Effects,
+        EventType::Touch,
+    ];
+
+    pub fn from_id(id: &str) -> Option<EventType> {
+        EventType::ALL
+            .into_iter()
+            .find(|event_type| (*event_type as u8).to_string() == id)
+    }
+}
+
+/// A change to the controller's state, such as from its touch buttons or the
+/// Nanoleaf app.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateChange {
+    On(bool),
+    Brightness(u32),
+    Hue(u32),
+    Saturation(u32),
+    ColorTemperature(u32),
+    ColorMode(String),
+}
+
+/// Panels were added, removed or rotated.
+#[derive(Debug, Clone)]
+pub enum LayoutChange {
+    Layout(Layout),
+    GlobalOrientation(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    SingleTap,
+    DoubleTap,
+    SwipeUp,
+    SwipeDown,
+    SwipeLeft,
+    SwipeRight,
+    Other(u32),
+}
+
+impl From<u32> for Gesture {
+    fn from(gesture: u32) -> Gesture {
+        match gesture {
+            0 => Gesture::SingleTap,
+            1 => Gesture::DoubleTap,
+            2 => Gesture::SwipeUp,
+            3 => Gesture::SwipeDown,
+            4 => Gesture::SwipeLeft,
+            5 => Gesture::SwipeRight,
+            other => Gesture::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum NanoleafEvent {
+    State(StateChange),
+    Layout(LayoutChange),
+    /// The name of the newly selected effect. Effects that aren't saved on
+    /// the controller have names like `*Solid*` or `*ExtControl*`.
+    Effect(String),
+    /// Swipes aren't tied to a panel.
+    Touch {
+        panel_id: Option<u16>,
+        gesture: Gesture,
+    },
+}
+
+/// A single attribute in a state, layout or effects event. What `attr` refers
+/// to, and the type of `value`, depend on the kind of event.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventAttribute {
+    pub attr: u32,
+    pub value: serde_json::Value,
+}
+
+/// A gesture on the panels. Swipes are reported with a panel ID of -1.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TouchAttribute {
+    #[serde(rename = "panelId")]
+    pub panel_id: i32,
+    pub gesture: u32,
+}
+
+/// The body of an event, whose entries depend on the kind of event.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventFrame {
+    pub events: Vec<serde_json::Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_palette_probabilities_only_when_set() {
+        let colors: Vec<PaletteColor> = serde_json::from_str(
+            r#"[{"hue":0,"saturation":100,"brightness":100,"probability":62.5},{"hue":240,"saturation":100,"brightness":100}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(colors[0].probability, Some(62.5));
+        assert_eq!(colors[1].probability, None);
+        assert_eq!(
+            serde_json::to_string(&colors).unwrap(),
+            r#"[{"hue":0,"saturation":100,"brightness":100,"probability":62.5},{"hue":240,"saturation":100,"brightness":100}]"#
+        );
+    }
 }
@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    color::RGBColor,
+    nanoleaf::{stream::PanelFrame, types::PanelLayout},
+};
+
+/// A point in the same space as Hue entertainment area positions: `x` runs
+/// from left (-1) to right (1), and `y` from bottom (-1) to top (1).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Position {
+    fn distance(&self, other: &Position) -> f32 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2)).sqrt()
+    }
+}
+
+/// How panels without a manual assignment pick their color.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutMode {
+    /// Each panel takes the color of the closest light.
+    #[default]
+    Nearest,
+    /// Each panel blends every light's color, weighted by how close it is.
+    Gradient,
+    /// Only assigned panels are lit, unless lights also have positions, in
+    /// which case the rest fall back to the nearest light.
+    Manual,
+}
+
+/// A light whose color is drawn on the panels.
+#[derive(Debug, Clone)]
+pub struct SpatialLight {
+    pub id: String,
+    pub position: Option<Position>,
+}
+
+/// Where a mapping's lights sit, and which panels are pinned to which lights.
+#[derive(Debug, Clone)]
+pub struct SpatialLayout {
+    pub mode: LayoutMode,
+    pub lights: Vec<SpatialLight>,
+    /// Panel IDs, and the index in `lights` of the light they show.
+    pub panels: BTreeMap<u16, usize>,
+    /// How long each panel fades to a new color, in tenths of a second.
+    pub transition_time: u16,
+    /// The ID of the entertainment area the positions came from, if any.
+    pub entertainment: Option<String>,
+}
+
+/// The current color of a light, at full brightness, along with its
+/// brightness so lights can be compared against each other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightColor {
+    pub color: RGBColor,
+    pub brightness: f32,
+    pub on: bool,
+}
+
+/// How much each light contributes to each panel of a single controller.
+#[derive(Debug, Clone)]
+pub struct PanelMap {
+    panels: Vec<(u16, Vec<(usize, f32)>)>,
+    transition_time: u16,
+}
+
+/// Rotate the panels by the layout's global orientation, then scale them to
+/// fill -1..1 on both axes so they line up with the lights' positions.
+fn normalize_panels(panel_layout: &PanelLayout) -> Vec<(u16, Position)> {
+    let angle = (panel_layout.global_orientation.value as f32).to_radians();
+    let (sin, cos) = angle.sin_cos();
+
+    let rotated: Vec<(u16, Position)> = panel_layout
+        .layout
+        .position_data
+        .iter()
+        .filter(|position| position.is_lit())
+        .map(|position| {
+            let (x, y) = (position.x as f32, position.y as f32);
+            (
+                position.panel_id,
+                Position {
+                    x: x * cos - y * sin,
+                    y: x * sin + y * cos,
+                },
+            )
+        })
+        .collect();
+
+    let bounds = rotated.iter().fold(None, |bounds, (_, position)| {
+        let (min_x, max_x, min_y, max_y) =
+            bounds.unwrap_or((position.x, position.x, position.y, position.y));
+        Some((
+            min_x.min(position.x),
+            max_x.max(position.x),
+            min_y.min(position.y),
+            max_y.max(position.y),
+        ))
+    });
+
+    let Some((min_x, max_x, min_y, max_y)) = bounds else {
+        return Vec::new();
+    };
+
+    // A single row or column of panels sits on the axis, rather than at one end.
+    let scale = |value: f32, min: f32, max: f32| {
+        if (max - min).abs() < f32::EPSILON {
+            0.0
+        } else {
+            (value - min) / (max - min) * 2.0 - 1.0
+        }
+    };
+
+    rotated
+        .into_iter()
+        .map(|(id, position)| {
+            (
+                id,
+                Position {
+                    x: scale(position.x, min_x, max_x),
+                    y: scale(position.y, min_y, max_y),
+                },
+            )
+        })
+        .collect()
+}
+
+impl PanelMap {
+    pub fn new(layout: &SpatialLayout, panel_layout: &PanelLayout) -> PanelMap {
+        let positioned: Vec<(usize, Position)> = layout
+            .lights
+            .iter()
+            .enumerate()
+            .filter_map(|(index, light)| light.position.map(|position| (index, position)))
+            .collect();
+
+        let nearest = |panel: &Position| {
+            positioned
+                .iter()
+                .min_by(|(_, a), (_, b)| panel.distance(a).total_cmp(&panel.distance(b)))
+                .map(|(index, _)| vec![(*index, 1.0)])
+                .unwrap_or_default()
+        };
+
+        let gradient = |panel: &Position| {
+            // Inverse distance weighting. A panel sitting right on top of a light
+            // would divide by zero, so it just takes that light's color.
+            if let Some((index, _)) = positioned
+                .iter()
+                .find(|(_, light)| panel.distance(light) < 1e-3)
+            {
+                return vec![(*index, 1.0)];
+            }
+
+            positioned
+                .iter()
+                .map(|(index, light)| (*index, 1.0 / panel.distance(light).powi(2)))
+                .collect()
+        };
+
+        let panels = normalize_panels(panel_layout)
+            .into_iter()
+            .map(|(id, panel)| {
+                let weights = match (layout.panels.get(&id), layout.mode) {
+                    (Some(index), _) => vec![(*index, 1.0)],
+                    (None, LayoutMode::Gradient) => gradient(&panel),
+                    (None, LayoutMode::Nearest | LayoutMode::Manual) => nearest(&panel),
+                };
+
+                (id, weights)
+            })
+            .collect();
+
+        PanelMap {
+            panels,
+            transition_time: layout.transition_time,
+        }
+    }
+
+    /// Blend the lights' colors into a frame for the controller. Colors are
+    /// scaled relative to the brightest light, since the controller's overall
+    /// brightness already follows the room. Lights whose color isn't known yet
+    /// are left out of the blend.
+    pub fn render(&self, colors: &[Option<LightColor>]) -> Vec<PanelFrame> {
+        let brightest = colors
+            .iter()
+            .flatten()
+            .filter(|light| light.on)
+            .map(|light| light.brightness)
+            .fold(0.0_f32, f32::max);
+
+        self.panels
+            .iter()
+            .map(|(id, weights)| {
+                let mut total = 0.0;
+                let mut rgb = [0.0_f32; 3];
+
+                for (index, weight) in weights {
+                    let Some(light) = colors.get(*index).copied().flatten() else {
+                        continue;
+                    };
+
+                    let level = if light.on && brightest > 0.0 {
+                        light.brightness / brightest
+                    } else {
+                        0.0
+                    };
+
+                    rgb[0] += weight * light.color.red as f32 * level;
+                    rgb[1] += weight * light.color.green as f32 * level;
+                    rgb[2] += weight * light.color.blue as f32 * level;
+                    total += weight;
+                }
+
+                let channel = |value: f32| {
+                    if total > 0.0 {
+                        (value / total).round().clamp(0.0, 255.0) as u8
+                    } else {
+                        0
+                    }
+                };
+
+                PanelFrame {
+                    panel_id: *id,
+                    red: channel(rgb[0]),
+                    green: channel(rgb[1]),
+                    blue: channel(rgb[2]),
+                    white: 0,
+                    transition_time: self.transition_time,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: &str = include_str!("../tests/fixtures/nanoleaf/layout.json");
+
+    const RED: RGBColor = RGBColor {
+        red: 255,
+        green: 0,
+        blue: 0,
+    };
+    const BLUE: RGBColor = RGBColor {
+        red: 0,
+        green: 0,
+        blue: 255,
+    };
+
+    fn panel_layout() -> PanelLayout {
+        serde_json::from_str(LAYOUT).unwrap()
+    }
+
+    fn assert_positions(actual: Vec<(u16, Position)>, expected: &[(u16, f32, f32)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for ((id, position), (expected_id, x, y)) in actual.iter().zip(expected) {
+            assert_eq!(id, expected_id);
+            assert!(
+                (position.x - x).abs() < 1e-4 && (position.y - y).abs() < 1e-4,
+                "panel {} is at {:?}, expected ({}, {})",
+                id,
+                position,
+                x,
+                y
+            );
+        }
+    }
+
+    /// One light in the bottom left corner, and another right of center.
+    fn layout(mode: LayoutMode) -> SpatialLayout {
+        SpatialLayout {
+            mode,
+            lights: vec![
+                SpatialLight {
+                    id: String::from("left"),
+                    position: Some(Position { x: -1.0, y: -1.0 }),
+                },
+                SpatialLight {
+                    id: String::from("right"),
+                    position: Some(Position { x: 1.0, y: 0.5 }),
+                },
+            ],
+            panels: BTreeMap::new(),
+            transition_time: 5,
+            entertainment: None,
+        }
+    }
+
+    fn weights(map: &PanelMap) -> Vec<(u16, Vec<usize>)> {
+        map.panels
+            .iter()
+            .map(|(id, weights)| (*id, weights.iter().map(|(index, _)| *index).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn fits_the_panels_into_the_lights_space() {
+        // The controller has no LEDs, so it's left out.
+        assert_positions(
+            normalize_panels(&panel_layout()),
+            &[
+                (2810, -1.0, -1.0),
+                (61433, 0.0, -1.0),
+                (4215, 1.0, -1.0),
+                (38117, -0.5, 1.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn turns_the_panels_with_the_layout() {
+        let mut panel_layout = panel_layout();
+        panel_layout.global_orientation.value = 90;
+
+        assert_positions(
+            normalize_panels(&panel_layout),
+            &[
+                (2810, 1.0, -1.0),
+                (61433, 1.0, 0.0),
+                (4215, 1.0, 1.0),
+                (38117, -1.0, -0.5),
+            ],
+        );
+    }
+
+    #[test]
+    fn centers_a_single_row_of_panels() {
+        let mut panel_layout = panel_layout();
+        panel_layout
+            .layout
+            .position_data
+            .retain(|panel| panel.panel_id != 38117);
+
+        assert_positions(
+            normalize_panels(&panel_layout),
+            &[(2810, -1.0, 0.0), (61433, 0.0, 0.0), (4215, 1.0, 0.0)],
+        );
+    }
+
+    #[test]
+    fn gives_each_panel_the_nearest_light() {
+        let map = PanelMap::new(&layout(LayoutMode::Nearest), &panel_layout());
+
+        assert_eq!(
+            weights(&map),
+            [
+                (2810, vec![0]),
+                (61433, vec![0]),
+                (4215, vec![1]),
+                (38117, vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn blends_every_light_into_a_gradient() {
+        let map = PanelMap::new(&layout(LayoutMode::Gradient), &panel_layout());
+
+        // The panel on top of a light takes its color outright.
+        assert_eq!(map.panels[0].1, [(0, 1.0)]);
+        assert_eq!(weights(&map)[1], (61433, vec![0, 1]));
+
+        let colors = [
+            Some(LightColor {
+                color: RED,
+                brightness: 100.0,
+                on: true,
+            }),
+            Some(LightColor {
+                color: BLUE,
+                brightness: 50.0,
+                on: true,
+            }),
+        ];
+        let frame = map.render(&colors);
+
+        // One unit away from the left light, and 1.8 from the right one,
+        // which is also half as bright.
+        assert_eq!(
+            frame[1],
+            PanelFrame {
+                panel_id: 61433,
+                red: 195,
+                green: 0,
+                blue: 30,
+                white: 0,
+                transition_time: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn only_lights_pinned_panels_in_manual_mode() {
+        let mut layout = layout(LayoutMode::Manual);
+        for light in &mut layout.lights {
+            light.position = None;
+        }
+        // A panel the controller doesn't have, and a light that isn't there.
+        layout.panels = BTreeMap::from([(38117, 0), (999, 1), (61433, 5)]);
+
+        let map = PanelMap::new(&layout, &panel_layout());
+        assert_eq!(
+            weights(&map),
+            [
+                (2810, vec![]),
+                (61433, vec![5]),
+                (4215, vec![]),
+                (38117, vec![0]),
+            ]
+        );
+
+        let colors = [
+            Some(LightColor {
+                color: RED,
+                brightness: 40.0,
+                on: true,
+            }),
+            None,
+        ];
+        let lit: Vec<(u16, u8)> = map
+            .render(&colors)
+            .iter()
+            .map(|frame| (frame.panel_id, frame.red))
+            .collect();
+        assert_eq!(lit, [(2810, 0), (61433, 0), (4215, 0), (38117, 255)]);
+    }
+
+    #[test]
+    fn falls_back_to_the_nearest_light_for_unpinned_panels() {
+        let mut layout = layout(LayoutMode::Manual);
+        layout.panels = BTreeMap::from([(2810, 1)]);
+
+        let map = PanelMap::new(&layout, &panel_layout());
+        assert_eq!(
+            weights(&map),
+            [
+                (2810, vec![1]),
+                (61433, vec![0]),
+                (4215, vec![1]),
+                (38117, vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_relative_to_the_brightest_light() {
+        let map = PanelMap::new(&layout(LayoutMode::Nearest), &panel_layout());
+
+        let colors = [
+            Some(LightColor {
+                color: RED,
+                brightness: 20.0,
+                on: true,
+            }),
+            Some(LightColor {
+                color: BLUE,
+                brightness: 80.0,
+                on: true,
+            }),
+        ];
+        let frames = map.render(&colors);
+        assert_eq!(
+            frames[0],
+            PanelFrame {
+                panel_id: 2810,
+                red: 64,
+                green: 0,
+                blue: 0,
+                white: 0,
+                transition_time: 5,
+            }
+        );
+        assert_eq!(frames[2].blue, 255);
+
+        // Lights that are off go dark, without affecting the others.
+        let colors = [
+            colors[0],
+            Some(LightColor {
+                on: false,
+                ..colors[1].unwrap()
+            }),
+        ];
+        let frames = map.render(&colors);
+        assert_eq!((frames[0].red, frames[2].blue), (255, 0));
+    }
+}
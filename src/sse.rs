@@ -0,0 +1,256 @@
+/// A single dispatched Server-Sent Events frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseFrame {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Incremental parser for `text/event-stream` bodies. Chunks can be fed in as
+/// they arrive from the network, and complete frames are returned once their
+/// terminating blank line has been seen.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    id: Option<String>,
+    event: Option<String>,
+    data: Vec<String>,
+    /// The ID of the most recent complete frame that had one. Unlike a frame's
+    /// own ID, this is also updated by frames without any data, which are
+    /// never returned.
+    last_event_id: Option<String>,
+    /// Whether the last chunk ended in a `\r`, in which case a `\n` at the
+    /// start of the next one belongs to the same line break.
+    pending_cr: bool,
+}
+
+impl SseParser {
+    pub fn new() -> SseParser {
+        SseParser::default()
+    }
+
+    /// The ID to send as `Last-Event-ID` when reconnecting.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseFrame> {
+        self.buffer.extend_from_slice(chunk);
+
+        if self.pending_cr && !self.buffer.is_empty() {
+            if self.buffer[0] == b'\n' {
+                self.buffer.remove(0);
+            }
+            self.pending_cr = false;
+        }
+
+        let mut frames = Vec::new();
+
+        // Only consume complete lines. Anything after the last line break is
+        // kept around until the next chunk arrives, which also protects us from
+        // multi-byte characters being split across chunks.
+        while let Some(position) = self.buffer.iter().position(|&b| b == b'\n' || b == b'\r') {
+            let line: Vec<u8> = self.buffer.drain(..position).collect();
+            let terminator = self.buffer.remove(0);
+
+            if terminator == b'\r' {
+                match self.buffer.first() {
+                    Some(b'\n') => {
+                        self.buffer.remove(0);
+                    }
+                    Some(_) => {}
+                    None => self.pending_cr = true,
+                }
+            }
+
+            let line = String::from_utf8_lossy(&line);
+            if let Some(frame) = self.process_line(&line) {
+                frames.push(frame);
+            }
+        }
+
+        frames
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseFrame> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+
+        // Lines starting with a colon are comments, used by the Hue bridge as
+        // a keep-alive (`: hi`).
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "id" => self.id = Some(value.to_string()),
+            "event" => self.event = Some(value.to_string()),
+            "data" => self.data.push(value.to_string()),
+            _ => {}
+        }
+
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseFrame> {
+        let id = self.id.take();
+        let event = self.event.take();
+
+        if id.is_some() {
+            self.last_event_id.clone_from(&id);
+        }
+
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let data = self.data.join("\n");
+        self.data.clear();
+
+        Some(SseFrame { id, event, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(id: Option<&str>, event: Option<&str>, data: &str) -> SseFrame {
+        SseFrame {
+            id: id.map(String::from),
+            event: event.map(String::from),
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_frames_with_every_line_ending() {
+        for ending in ["\n", "\r", "\r\n"] {
+            let body = ["id: 1", "data: first", "", "id: 2", "data: second", "", ""].join(ending);
+            let mut parser = SseParser::new();
+
+            assert_eq!(
+                parser.feed(body.as_bytes()),
+                vec![
+                    frame(Some("1"), None, "first"),
+                    frame(Some("2"), None, "second"),
+                ],
+                "line ending {:?}",
+                ending
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_id_when_a_crlf_is_split_across_chunks() {
+        let mut parser = SseParser::new();
+
+        assert!(parser.feed(b"id: 1700000000:0\r").is_empty());
+        assert!(parser.feed(b"\ndata: [1]\r").is_empty());
+        assert_eq!(
+            parser.feed(b"\n\r\n"),
+            vec![frame(Some("1700000000:0"), None, "[1]")]
+        );
+    }
+
+    #[test]
+    fn keeps_partial_lines_until_they_are_complete() {
+        let body = "event: update\ndata: {\"name\": \"caf\u{e9}\"}\n\n".as_bytes();
+        let mut parser = SseParser::new();
+        let mut frames = Vec::new();
+
+        // One byte at a time, which also splits the two-byte character.
+        for byte in body {
+            frames.extend(parser.feed(std::slice::from_ref(byte)));
+        }
+
+        assert_eq!(
+            frames,
+            vec![frame(None, Some("update"), "{\"name\": \"caf\u{e9}\"}")]
+        );
+    }
+
+    #[test]
+    fn joins_multi_line_data() {
+        let mut parser = SseParser::new();
+
+        assert_eq!(
+            parser.feed(b"data: [\ndata:1,\ndata:  2]\n\n"),
+            vec![frame(None, None, "[\n1,\n 2]")]
+        );
+    }
+
+    #[test]
+    fn skips_comments_and_empty_frames() {
+        let mut parser = SseParser::new();
+
+        assert!(parser.feed(b": hi\n\n: hi\n\n").is_empty());
+        assert!(parser.feed(b"retry: 1000\n\n").is_empty());
+        assert_eq!(
+            parser.feed(b": hi\nid: 3\n: hi\ndata: x\n\n"),
+            vec![frame(Some("3"), None, "x")]
+        );
+    }
+
+    #[test]
+    fn remembers_the_id_of_frames_without_data() {
+        let mut parser = SseParser::new();
+
+        assert_eq!(
+            parser.feed(
+                b"id: 1
+data: x
+
+"
+            ),
+            vec![frame(Some("1"), None, "x")]
+        );
+        assert_eq!(parser.last_event_id(), Some("1"));
+
+        // Not until the frame is complete.
+        assert!(parser
+            .feed(
+                b"id: 2
+"
+            )
+            .is_empty());
+        assert_eq!(parser.last_event_id(), Some("1"));
+
+        // Nothing is dispatched, but a reconnect should resume from here.
+        assert!(parser
+            .feed(
+                b"id: 2
+
+"
+            )
+            .is_empty());
+        assert_eq!(parser.last_event_id(), Some("2"));
+
+        // The next frame doesn't inherit the ID.
+        assert_eq!(
+            parser.feed(
+                b"data: y
+
+"
+            ),
+            vec![frame(None, None, "y")]
+        );
+        assert_eq!(parser.last_event_id(), Some("2"));
+    }
+
+    #[test]
+    fn treats_a_field_without_a_colon_as_empty() {
+        let mut parser = SseParser::new();
+
+        assert_eq!(
+            parser.feed(b"data\ndata\n\n"),
+            vec![frame(None, None, "\n")]
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::{Stream, StreamExt};
+use log::{error, info, warn};
+use rand::Rng;
+use tokio::time::Instant;
+
+use crate::{
+    discovery::{self, DeviceKind},
+    hue::{client::Hue, error::HueError, eventstream::EventStream, types::Event},
+    nanoleaf::{
+        client::Nanoleaf,
+        eventstream::EventStream as NanoleafEventStream,
+        types::{EventType, NanoleafEvent},
+    },
+};
+
+/// Exponential backoff with equal jitter: each delay is somewhere between half
+/// and all of the current exponential step, capped at `max`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            attempt: 0,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let step = self
+            .initial
+            .saturating_mul(2_u32.saturating_pow(self.attempt))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = step / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+#[derive(Debug)]
+pub enum Supervised {
+    /// The event stream was re-established after an outage. Any state derived
+    /// from earlier events may be stale and should be re-fetched.
+    Reconnected,
+    Event(Event),
+}
+
+/// Keeps an event stream to the Hue bridge open, reconnecting with backoff
+/// whenever the connection drops.
+pub struct EventSupervisor<'a> {
+    hue: &'a Hue,
+    stream: Option<EventStream>,
+    last_event_id: Option<String>,
+    backoff: Backoff,
+    has_connected: bool,
+    bridge_id: Option<String>,
+    /// When to try connecting again, after a failed attempt or a dropped
+    /// connection.
+    retry_at: Option<Instant>,
+}
+
+impl<'a> EventSupervisor<'a> {
+    pub fn new(hue: &'a Hue, backoff: Backoff) -> EventSupervisor<'a> {
+        EventSupervisor {
+            hue,
+            stream: None,
+            last_event_id: None,
+            backoff,
+            has_connected: false,
+            bridge_id: None,
+            retry_at: None,
+        }
+    }
+
+    /// Look the bridge up on the network by its ID whenever it can't be reached,
+    /// in case its address has changed.
+    pub fn with_bridge_id(mut self, bridge_id: Option<String>) -> EventSupervisor<'a> {
+        self.bridge_id = bridge_id;
+        self
+    }
+
+    async fn reresolve(&self) {
+        let Some(bridge_id) = &self.bridge_id else {
+            return;
+        };
+
+        if let Some(host) = discovery::resolve(DeviceKind::HueBridge, bridge_id).await {
+            info!(target: "nanohue", "Found Hue bridge {} at {}.", bridge_id, host);
+            self.hue.set_host(&host);
+        }
+    }
+
+    /// Wait for the next event from the bridge. Only errors that cannot be
+    /// fixed by retrying, such as a revoked application key, are returned.
+    ///
+    /// This is not cancel safe: dropping the future while it is connecting
+    /// abandons the attempt, and the next call starts over. Use `into_stream`
+    /// from `tokio::select!`.
+    pub async fn next(&mut self) -> Result<Supervised, HueError> {
+        loop {
+            if let Some(retry_at) = self.retry_at {
+                tokio::time::sleep_until(retry_at).await;
+                self.retry_at = None;
+            }
+
+            if let Some(stream) = &mut self.stream {
+                match stream.next().await {
+                    Some(event) => {
+                        self.backoff.reset();
+                        return Ok(Supervised::Event(event));
+                    }
+                    None => {
+                        self.last_event_id = stream.last_event_id().map(String::from);
+                        self.stream = None;
+
+                        let delay = self.backoff.next_delay();
+                        warn!(
+                            target: "nanohue",
+                            "Event stream disconnected. Reconnecting in {:?}.",
+                            delay
+                        );
+                        self.retry_at = Some(Instant::now() + delay);
+                        continue;
+                    }
+                }
+            }
+
+            match self.hue.event_stream(self.last_event_id.as_deref()).await {
+                Ok(stream) => {
+                    self.stream = Some(stream);
+
+                    if self.has_connected {
+                        info!(target: "nanohue", "Reconnected to the Hue event stream.");
+                        return Ok(Supervised::Reconnected);
+                    }
+                    self.has_connected = true;
+                }
+                Err(err) if err.is_retryable() => {
+                    let delay = self.backoff.next_delay();
+                    warn!(
+                        target: "nanohue",
+                        "Unable to open the event stream. Retrying in {:?}. {}",
+                        delay,
+                        err
+                    );
+                    self.retry_at = Some(Instant::now() + delay);
+                    self.reresolve().await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Turn the supervisor into a stream of its events, which ends after the
+    /// first error. The stream keeps any in-flight connection attempt between
+    /// polls, so unlike `next`, `StreamExt::next` on it is cancel safe.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Supervised, HueError>> + 'a {
+        futures_util::stream::unfold(Some(self), |supervisor| async move {
+            let mut supervisor = supervisor?;
+            match supervisor.next().await {
+                Ok(supervised) => Some((Ok(supervised), Some(supervisor))),
+                Err(err) => Some((Err(err), None)),
+            }
+        })
+    }
+}
+
+/// Keeps an event stream from a Nanoleaf controller open, reconnecting with
+/// backoff whenever the connection drops.
+pub struct NanoleafSupervisor {
+    name: String,
+    nanoleaf: Arc<Nanoleaf>,
+    types: Vec<EventType>,
+    stream: Option<NanoleafEventStream>,
+    backoff: Backoff,
+    device_id: Option<String>,
+    retry_at: Option<Instant>,
+}
+
+impl NanoleafSupervisor {
+    pub fn new(
+        name: &str,
+        nanoleaf: Arc<Nanoleaf>,
+        types: &[EventType],
+        backoff: Backoff,
+    ) -> NanoleafSupervisor {
+        NanoleafSupervisor {
+            name: name.to_string(),
+            nanoleaf,
+            types: types.to_vec(),
+            stream: None,
+            backoff,
+            device_id: None,
+            retry_at: None,
+        }
+    }
+
+    /// Look the controller up on the network by its ID whenever it can't be
+    /// reached, in case its address has changed.
+    pub fn with_device_id(mut self, device_id: Option<String>) -> NanoleafSupervisor {
+        self.device_id = device_id;
+        self
+    }
+
+    async fn reresolve(&mut self) {
+        let Some(device_id) = &self.device_id else {
+            return;
+        };
+
+        if let Some(host) = discovery::resolve(DeviceKind::Nanoleaf, device_id).await {
+            info!(target: "nanohue", "Found Nanoleaf {} at {}.", device_id, host);
+            self.nanoleaf.set_host(&host);
+        }
+    }
+
+    /// Wait for the next event from the controller. Returns `None` if the
+    /// controller refuses the connection in a way that retrying won't fix,
+    /// such as a revoked auth token.
+    pub async fn next(&mut self) -> Option<NanoleafEvent> {
+        loop {
+            if let Some(retry_at) = self.retry_at {
+                tokio::time::sleep_until(retry_at).await;
+                self.retry_at = None;
+            }
+
+            if let Some(stream) = &mut self.stream {
+                match stream.next().await {
+                    Some(event) => {
+                        self.backoff.reset();
+                        return Some(event);
+                    }
+                    None => {
+                        self.stream = None;
+
+                        let delay = self.backoff.next_delay();
+                        warn!(
+                            target: "nanohue",
+                            "Event stream from the Nanoleaf '{}' disconnected. Reconnecting in {:?}.",
+                            self.name,
+                            delay
+                        );
+                        self.retry_at = Some(Instant::now() + delay);
+                        continue;
+                    }
+                }
+            }
+
+            match self.nanoleaf.events(&self.types).await {
+                Ok(stream) => self.stream = Some(stream),
+                Err(err) if err.is_retryable() => {
+                    let delay = self.backoff.next_delay();
+                    warn!(
+                        target: "nanohue",
+                        "Unable to open the event stream from the Nanoleaf '{}'. Retrying in {:?}. {}",
+                        self.name,
+                        delay,
+                        err
+                    );
+                    self.retry_at = Some(Instant::now() + delay);
+                    self.reresolve().await;
+                }
+                Err(err) => {
+                    error!(
+                        target: "nanohue",
+                        "Unable to open the event stream from the Nanoleaf '{}'. {}",
+                        self.name,
+                        err
+                    );
+                    return None;
+                }
+            }
+        }
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = NanoleafEvent> {
+        futures_util::stream::unfold(self, |mut supervisor| async move {
+            let event = supervisor.next().await?;
+            Some((event, supervisor))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::testing::{Reply, StandIn};
+
+    fn event_frame(id: &str, grouped_light: &str) -> String {
+        format!(
+            "id: {}\ndata: [{{\"creationtime\":\"2024-01-01T00:00:00Z\",\"id\":\"{}\",\"type\":\"update\",\"data\":[{{\"id\":\"{}\",\"type\":\"grouped_light\"}}]}}]\n\n",
+            id, id, grouped_light
+        )
+    }
+
+    fn event_id(supervised: Supervised) -> String {
+        match supervised {
+            Supervised::Event(event) => event.data[0].id.clone(),
+            Supervised::Reconnected => panic!("expected an event, got a reconnect"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_with_backoff_after_the_stream_drops() {
+        let connections = AtomicUsize::new(0);
+        let first = event_frame("1:0", "gl1");
+        let second = event_frame("2:0", "gl2");
+        let third = event_frame("3:0", "gl3");
+
+        let bridge = StandIn::start(move |_| match connections.fetch_add(1, Ordering::SeqCst) {
+            // The bridge is still starting up.
+            0 => Reply::status(503),
+            // Dropped after two events.
+            1 => Reply::events(&[&first, &second]),
+            _ => Reply::events(&[&third]),
+        })
+        .await;
+
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(100));
+        let mut supervisor = EventSupervisor::new(&hue, backoff);
+
+        let started = Instant::now();
+        assert_eq!(event_id(supervisor.next().await.unwrap()), "gl1");
+        // At least half a step is waited out after the failed attempt.
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(event_id(supervisor.next().await.unwrap()), "gl2");
+
+        let dropped = Instant::now();
+        assert!(matches!(
+            supervisor.next().await.unwrap(),
+            Supervised::Reconnected
+        ));
+        assert!(dropped.elapsed() >= Duration::from_millis(50));
+        assert_eq!(event_id(supervisor.next().await.unwrap()), "gl3");
+
+        let requests = bridge.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].header("last-event-id"), None);
+        // The new connection picks up after the last event seen.
+        assert_eq!(requests[2].header("last-event-id"), Some("2:0"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_the_key_is_rejected() {
+        let bridge = StandIn::start(|_| Reply::status(403)).await;
+
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+        let mut supervisor = EventSupervisor::new(&hue, Backoff::default());
+
+        assert!(matches!(
+            supervisor.next().await,
+            Err(HueError::Unauthorized)
+        ));
+        assert_eq!(bridge.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_connecting_while_other_branches_win() {
+        let frame = event_frame("1:0", "gl1");
+        let bridge =
+            StandIn::start(move |_| Reply::events(&[&frame]).after(Duration::from_millis(200)))
+                .await;
+
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+        let events = EventSupervisor::new(&hue, Backoff::default()).into_stream();
+        tokio::pin!(events);
+
+        // Something else is always ready well before the bridge answers, as
+        // with the sampling interval in `run_bridge`.
+        let mut ticks = tokio::time::interval(Duration::from_millis(10));
+        let event = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                tokio::select! {
+                    Some(event) = events.next() => break event,
+                    _ = ticks.tick() => {}
+                }
+            }
+        })
+        .await
+        .expect("the connection attempt was cancelled by every tick");
+
+        assert_eq!(event_id(event.unwrap()), "gl1");
+        // The connection was never abandoned and reopened.
+        assert_eq!(bridge.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ends_the_stream_after_an_error() {
+        let bridge = StandIn::start(|_| Reply::status(401)).await;
+
+        let hue = Hue::new(String::from("bridge"), String::from("key"))
+            .unwrap()
+            .with_url(&bridge.url());
+        let events = EventSupervisor::new(&hue, Backoff::default()).into_stream();
+        tokio::pin!(events);
+
+        assert!(matches!(
+            events.next().await,
+            Some(Err(HueError::Unauthorized))
+        ));
+        assert!(events.next().await.is_none());
+    }
+}
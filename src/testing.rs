@@ -0,0 +1,203 @@
+//! A local stand-in for the Hue bridge and Nanoleaf controllers, so that the
+//! clients can be tested against real HTTP connections.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// A request received by the stand-in.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// What the stand-in sends back for a request.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// A JSON body with the given status code.
+    Json(u16, String),
+    /// An event stream, written one chunk at a time. The connection is closed
+    /// after the last chunk, unless it is held open.
+    Events { chunks: Vec<String>, hold: bool },
+    /// Another reply, sent only after a while, like a bridge that is slow to
+    /// answer.
+    Delayed(Duration, Box<Reply>),
+}
+
+impl Reply {
+    pub fn status(status: u16) -> Reply {
+        Reply::Json(status, String::from("{}"))
+    }
+
+    pub fn events(chunks: &[&str]) -> Reply {
+        Reply::Events {
+            chunks: chunks.iter().map(|chunk| chunk.to_string()).collect(),
+            hold: false,
+        }
+    }
+
+    pub fn after(self, delay: Duration) -> Reply {
+        Reply::Delayed(delay, Box::new(self))
+    }
+}
+
+/// How long to wait between the chunks of an event stream, so that each
+/// arrives on its own.
+const CHUNK_INTERVAL: Duration = Duration::from_millis(20);
+
+type Handler = dyn Fn(&Request) -> Reply + Send + Sync;
+
+pub struct StandIn {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl StandIn {
+    /// Listen on a free local port, answering each request with `reply`.
+    pub async fn start<F>(reply: F) -> StandIn
+    where
+        F: Fn(&Request) -> Reply + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(reply);
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, handler.clone(), received.clone()));
+            }
+        });
+
+        StandIn { addr, requests }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Every request received so far, in order.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve(mut socket: TcpStream, handler: Arc<Handler>, requests: Arc<Mutex<Vec<Request>>>) {
+    let Some(request) = read_request(&mut socket).await else {
+        return;
+    };
+    requests.lock().unwrap().push(request.clone());
+
+    let mut reply = handler(&request);
+    while let Reply::Delayed(delay, delayed) = reply {
+        tokio::time::sleep(delay).await;
+        reply = *delayed;
+    }
+
+    match reply {
+        Reply::Json(status, body) => {
+            let response = format!(
+                "HTTP/1.1 {} Stand-in\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+        Reply::Events { chunks, hold } => {
+            let head =
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n";
+            if socket.write_all(head.as_bytes()).await.is_err() {
+                return;
+            }
+
+            for chunk in chunks {
+                tokio::time::sleep(CHUNK_INTERVAL).await;
+                if socket.write_all(chunk.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+
+            if hold {
+                // Until the client hangs up.
+                let _ = socket.read(&mut [0; 1]).await;
+            }
+        }
+        Reply::Delayed(..) => unreachable!(),
+    }
+
+    let _ = socket.shutdown().await;
+}
+
+async fn read_request(socket: &mut TcpStream) -> Option<Request> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+
+    let header_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+        let read = socket.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: String::new(),
+    };
+
+    let length: usize = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = buffer.split_off(header_end + 4);
+    while body.len() < length {
+        let read = socket.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    request.body = String::from_utf8_lossy(&body).to_string();
+
+    Some(request)
+}